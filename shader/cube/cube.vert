@@ -0,0 +1,20 @@
+#version 450
+
+layout (location = 0) in vec3 i_pos;
+layout (location = 1) in vec3 i_color;
+
+// 矩阵由directx_math按行存储，这里按列读取后正好是转置，直接左乘
+layout (set = 0, binding = 0) uniform CubeUniform {
+    mat4 mvp;
+} u_cube;
+
+layout (location = 0) out vec3 o_color;
+out gl_PerVertex {
+    vec4 gl_Position;
+};
+
+void main()
+{
+    gl_Position = u_cube.mvp * vec4(i_pos, 1.0);
+    o_color = i_color;
+}
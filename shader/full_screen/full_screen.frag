@@ -3,4 +3,11 @@
 layout (location = 0) in vec2 i_uv;
 
 layout (set = 0, binding = 0) uniform texture2D tex_screen;
-layout (set = 0, binding = 0) uniform
\ No newline at end of file
+layout (set = 0, binding = 1) uniform sampler sampler_screen;
+
+layout (location = 0) out vec4 out_color;
+
+void main()
+{
+    out_color = texture(sampler2D(tex_screen, sampler_screen), i_uv);
+}
@@ -2,42 +2,64 @@ pub use winit;
 use ash::vk;
 use ash::version::*;
 use crate::base::ri;
+use crate::base::device_selector;
+use crate::base::validation;
 use crate::base::surface;
 use crate::base::buffer;
-use std::time;
+use crate::base::descriptor;
+use crate::base::render_target;
+use crate::base::queue;
+use crate::base::frame;
+use crate::base::profiler;
+use crate::base::clock;
+use crate::base::input;
+use crate::base::loader;
+use crate::base::pso;
+use crate::base::utility;
+use crate::base::error::Result;
 use std::boxed;
 use std::cell::Cell;
-use std::cell::RefCell;
+use std::cell::{Ref, RefCell};
 use std::rc::Rc;
 
 
 
 pub struct App {
-    pub window: winit::Window,
-    pub backend: RefCell<Rc<ri::Backend>>,
-    pub surface: surface::Surface,
+    // 无窗口模式下window、surface、events_loop都为None
+    pub window: Option<winit::Window>,
+    pub surface: Option<RefCell<surface::Surface>>,
     pub buf_mgr_sys: buffer::BufferManagerSystem,
+    // 各RenderLoop共用，按需增加descriptor pool
+    pub descriptor_allocator: descriptor::DescriptorAllocator,
     // other
-    pub cmd_pool: vk::CommandPool,
-    pub present_complete: vk::Semaphore,
-    pub render_complete: vk::Semaphore,
-    pub render_loop_obj: boxed::Box<dyn RenderLoop>,
+    pub cmd_pool: vk::CommandPool, // graphic队列族
+    pub compute_cmd_pool: vk::CommandPool,
+    pub transfer_cmd_pool: vk::CommandPool,
+    pub render_loop_obj: RefCell<boxed::Box<dyn RenderLoop>>,
     // graphic queue info
     pub graphic_queue: vk::Queue,
-    pub graphic_cmd_buffer: vk::CommandBuffer,
+    // frames in flight，graphic command buffer和同步对象按帧轮换
+    pub frames: Vec<frame::FrameResources>,
     // compute queue info
     pub compute_queue: vk::Queue,
     pub compute_cmd_buffer: vk::CommandBuffer,
     // transfer
     pub transfer_queue: vk::Queue,
     pub transfer_cmd_buffer: vk::CommandBuffer,
-    pub graphic_submit_fence: vk::Fence,
+    pub profiler: profiler::GpuProfiler,
     // property
-    frame_count: Cell<u64>,
-    frame_timer: Cell<f64>, //  一帧耗时(ms)
-    timer_scale: Cell<f64>,
+    frame_index: Cell<usize>,
+    // swapchain过期或窗口尺寸变化，下一帧开始前重建
+    resize_pending: Cell<bool>,
+    clock: RefCell<clock::FrameClock>,
+    input: RefCell<input::InputState>,
+    // 关闭热重载时为None
+    shader_watcher: Option<RefCell<loader::ShaderWatcher>>,
+    headless_resolution: vk::Extent2D,
 
-    events_loop: RefCell<winit::EventsLoop>,
+    events_loop: Option<RefCell<winit::EventsLoop>>,
+    // 字段按声明顺序析构，backend放在最后，保证其他资源先于device销毁
+    pub backend: RefCell<Rc<ri::Backend>>,
 }
 
 #[derive(Default)]
@@ -48,15 +70,42 @@ pub struct AppCreateInfo {
     pub title: String,
     pub width: f32,
     pub height: f32,
+    pub device_selector: device_selector::DeviceSelector,
+    pub frames_in_flight: usize,
+    pub swapchain: surface::SwapchainConfig,
+    pub validation: validation::ValidationConfig,
+    // 固定update步长（秒），None表示每帧调用一次update
+    pub fixed_update_step: Option<f64>,
+    // 着色器源文件改动后重新编译并重建PSO，默认只在debug构建开启
+    pub shader_hot_reload: bool,
+}
+
+impl ::std::default::Default for AppCreateInfo {
+    fn default() -> Self {
+        AppCreateInfo {
+            app_name: "rt_vk_example".to_string(),
+            title: "rt_vk_example".to_string(),
+            width: 800.0,
+            height: 600.0,
+            device_selector: device_selector::DeviceSelector::default(),
+            frames_in_flight: 2,
+            swapchain: surface::SwapchainConfig::default(),
+            validation: validation::ValidationConfig::default(),
+            fixed_update_step: None,
+            shader_hot_reload: cfg!(debug_assertions),
+        }
+    }
 }
 
 static VERTEX_BUFFER_SIZE: u64 = 4 * 1024 * 1024;
 static INDEX_BUFFER_SIZE: u64 = 4 * 1024 * 1024;
 static UNIFORM_BUFFER_SIZE: u64 = 1024 * 1024;
+static TRANSIENT_BUFFER_SIZE: u64 = 256 * 1024;
+static SHADER_POLL_INTERVAL_MS: u64 = 500;
 
 
 impl App {
-    pub fn new(ci: &AppCreateInfo) -> Self
+    pub fn new(ci: &AppCreateInfo) -> Result<Self>
     {
         let events_loop = winit::EventsLoop::new();
         let window = {
@@ -68,30 +117,37 @@ impl App {
                         ci.height as f64
                     )
                 )
-                .build(&events_loop)
-                .unwrap()
+                .build(&events_loop)?
         };
-        let backend = Rc::new(ri::Backend::new(&window, 0));
-        let surface = surface::Surface::new(backend.clone(), &window);
-        let cmd_pool = unsafe {
+        let backend = Rc::new(ri::Backend::new(&window, &ci.device_selector, &ci.validation)?);
+        let surface = surface::Surface::new(backend.clone(), &window, &ci.swapchain)?;
+        App::create(backend, Some(window), Some(surface), Some(events_loop), ci)
+    }
+
+    pub fn new_headless(ci: &AppCreateInfo) -> Result<Self>
+    {
+        let backend = Rc::new(ri::Backend::new_headless(&ci.device_selector, &ci.validation)?);
+        App::create(backend, None, None, None, ci)
+    }
+
+    fn create(backend: Rc<ri::Backend>,
+              window: Option<winit::Window>,
+              surface: Option<surface::Surface>,
+              events_loop: Option<winit::EventsLoop>,
+              ci: &AppCreateInfo)
+        -> Result<Self>
+    {
+        let create_cmd_pool = |queue_family_index: u32| unsafe {
             let pool_ci  = vk::CommandPoolCreateInfo {
                 flags: vk::CommandPoolCreateFlags::RESET_COMMAND_BUFFER,
-                queue_family_index: backend.queue_family_index.clone(),
+                queue_family_index,
                 ..Default::default()
             };
-            backend.device.create_command_pool(&pool_ci, None).unwrap()
-        };
-        let semaphore_ci = vk::SemaphoreCreateInfo::default();
-        let present_complete = unsafe {
-            backend.device
-                .create_semaphore(&semaphore_ci, None)
-                .unwrap()
-        };
-        let render_complete = unsafe {
-            backend.device
-                .create_semaphore(&semaphore_ci, None)
-                .unwrap()
+            backend.device.create_command_pool(&pool_ci, None)
         };
+        let cmd_pool = create_cmd_pool(backend.queue_family_index)?;
+        let compute_cmd_pool = create_cmd_pool(backend.compute_queue_family_index)?;
+        let transfer_cmd_pool = create_cmd_pool(backend.transfer_queue_family_index)?;
         let render_loop_obj = boxed::Box::new(DefaultRenderLoop::default());
         let buf_mgr_sys = {
             buffer::BufferManagerSystem::new(
@@ -99,185 +155,493 @@ impl App {
                 VERTEX_BUFFER_SIZE,
                 INDEX_BUFFER_SIZE,
                 UNIFORM_BUFFER_SIZE,
-            )
+            )?
         };
+        let descriptor_allocator = descriptor::DescriptorAllocator::new(&backend.device);
         let graphic_queue = unsafe {
             backend.device.get_device_queue(backend.queue_family_index, 0)
         };
         let compute_queue = unsafe {
-            backend.device.get_device_queue(backend.queue_family_index, 0)
+            backend.device.get_device_queue(backend.compute_queue_family_index, 0)
         };
         let transfer_queue = unsafe {
-            backend.device.get_device_queue(backend.queue_family_index, 0)
+            backend.device.get_device_queue(backend.transfer_queue_family_index, 0)
         };
-        let (graphic_cmd_buffer,
-            compute_cmd_buffer,
-            transfer_cmd_buffer) = {
+        let allocate_cmd_buffer = |command_pool: vk::CommandPool| unsafe {
             let ci = vk::CommandBufferAllocateInfo {
-                command_buffer_count: 3,
-                command_pool: cmd_pool,
+                command_buffer_count: 1,
+                command_pool,
                 level: vk::CommandBufferLevel::PRIMARY,
                 ..Default::default()
             };
-            unsafe {
-                let cmd_bufs = backend.device.allocate_command_buffers(&ci)
-                    .unwrap();
-                (cmd_bufs[0], cmd_bufs[1], cmd_bufs[2])
-            }
-        };
-        let graphic_submit_fence = unsafe {
-            backend.device.create_fence(
-                &vk::FenceCreateInfo::default(), None)
-                .unwrap()
+            backend.device.allocate_command_buffers(&ci)
+                .map(|cmd_bufs| cmd_bufs[0])
         };
+        let compute_cmd_buffer = allocate_cmd_buffer(compute_cmd_pool)?;
+        let transfer_cmd_buffer = allocate_cmd_buffer(transfer_cmd_pool)?;
+        let frames = (0..std::cmp::max(1, ci.frames_in_flight))
+            .map(|i| frame::FrameResources::new(
+                &backend, cmd_pool, TRANSIENT_BUFFER_SIZE, &format!("frame_{}", i)))
+            .collect::<Result<Vec<frame::FrameResources>>>()?;
+        let profiler = profiler::GpuProfiler::new(&backend, frames.len())?;
 
-        App {
+        backend.set_object_name(cmd_pool, "graphic_cmd_pool");
+        backend.set_object_name(compute_cmd_pool, "compute_cmd_pool");
+        backend.set_object_name(transfer_cmd_pool, "transfer_cmd_pool");
+        backend.set_object_name(graphic_queue, "graphic_queue");
+        backend.set_object_name(compute_cmd_buffer, "compute_cmd_buffer");
+        backend.set_object_name(transfer_cmd_buffer, "transfer_cmd_buffer");
+        // 回退到graphic队列族时是同一个队列，不覆盖它的名字
+        if backend.has_dedicated_compute_queue() {
+            backend.set_object_name(compute_queue, "compute_queue");
+        }
+        if backend.has_dedicated_transfer_queue() {
+            backend.set_object_name(transfer_queue, "transfer_queue");
+        }
+
+        Ok(App {
             window,
             backend: RefCell::new(backend),
-            surface,
+            surface: surface.map(RefCell::new),
             cmd_pool,
-            present_complete,
-            render_complete,
-            render_loop_obj,
-            events_loop: RefCell::new(events_loop),
+            compute_cmd_pool,
+            transfer_cmd_pool,
+            render_loop_obj: RefCell::new(render_loop_obj),
+            events_loop: events_loop.map(RefCell::new),
             buf_mgr_sys,
+            descriptor_allocator,
             graphic_queue,
-            graphic_cmd_buffer,
+            frames,
             compute_queue,
             compute_cmd_buffer,
             transfer_queue,
             transfer_cmd_buffer,
-            graphic_submit_fence,
-            frame_count: Cell::new(0u64),
-            frame_timer: Cell::new(0.0),
-            timer_scale: Cell::new(1.0),
+            profiler,
+            frame_index: Cell::new(0),
+            resize_pending: Cell::new(false),
+            clock: RefCell::new(clock::FrameClock::new(ci.fixed_update_step)),
+            input: RefCell::new(input::InputState::new()),
+            shader_watcher: if ci.shader_hot_reload {
+                let poll_interval = std::time::Duration::from_millis(SHADER_POLL_INTERVAL_MS);
+                Some(RefCell::new(loader::ShaderWatcher::new(poll_interval)))
+            } else {
+                None
+            },
+            headless_resolution: vk::Extent2D {
+                width: ci.width as u32,
+                height: ci.height as u32,
+            },
+        })
+    }
+
+    pub fn is_headless(&self) -> bool
+    {
+        self.surface.is_none()
+    }
+
+    // 渲染分辨率，无窗口模式下使用AppCreateInfo中的宽高
+    pub fn resolution(&self) -> vk::Extent2D
+    {
+        match &self.surface {
+            Some(surface) => surface.borrow().surface_resolution,
+            None => self.headless_resolution,
         }
     }
 
-    fn window_resize(&self)
+    // 实际选用的swapchain参数，无窗口模式下为None
+    pub fn swapchain_info(&self) -> Option<surface::SwapchainInfo>
     {
+        self.surface.as_ref().map(|surface| surface.borrow().info())
     }
-}
 
+    // 这一帧的键盘鼠标状态，无窗口模式下始终为空
+    pub fn input(&self) -> Ref<'_, input::InputState>
+    {
+        self.input.borrow()
+    }
 
-impl Drop for App {
-    fn drop(&mut self) {
-        unsafe {
-            let device = &self.backend.borrow().device;
-            device.device_wait_idle();
-            device.destroy_command_pool(self.cmd_pool, None);
-            device.destroy_semaphore(self.present_complete, None);
-            device.destroy_semaphore(self.render_complete, None);
-            device.destroy_fence(self.graphic_submit_fence, None);
+    // 上一帧到这一帧的实际时间（秒）
+    pub fn delta_time(&self) -> f64
+    {
+        self.clock.borrow().delta
+    }
+
+    // 缩放后的累计时间（秒）
+    pub fn elapsed_time(&self) -> f64
+    {
+        self.clock.borrow().elapsed
+    }
+
+    pub fn fps(&self) -> f64
+    {
+        self.clock.borrow().fps
+    }
+
+    pub fn frame_count(&self) -> u64
+    {
+        self.clock.borrow().frame_count
+    }
+
+    pub fn time_scale(&self) -> f64
+    {
+        self.clock.borrow().time_scale
+    }
+
+    // 0暂停，小于1慢动作，只影响update收到的时间，不影响FPS统计
+    pub fn set_time_scale(&self, time_scale: f64)
+    {
+        self.clock.borrow_mut().time_scale = time_scale.max(0.0);
+    }
+
+    // 固定步长模式下渲染插值用的比例
+    pub fn fixed_update_alpha(&self) -> f64
+    {
+        self.clock.borrow().alpha()
+    }
+
+    pub fn validation_error_count(&self) -> usize
+    {
+        self.backend.borrow().validation_error_count()
+    }
+
+    pub fn current_frame(&self) -> &frame::FrameResources
+    {
+        &self.frames[self.frame_index.get()]
+    }
+
+    // 在frame_ctx所属帧的临时buffer上分配uniform数据，下次轮到这一帧时自动回收
+    // 需要begin_frame返回的frame_ctx，保证这一帧的fence已经等待过；update中不能分配
+    pub fn allocate_transient_uniform<T>(&self, frame_ctx: &frame::FrameContext, size: u64)
+        -> buffer::BufferSlice<T>
+    {
+        self.frames[frame_ctx.frame_index].allocate_transient::<T>(size)
+    }
+
+    // 无窗口模式下连续渲染frame_count帧
+    pub fn run_frames(&self, frame_count: u64) -> Result<()>
+    {
+        for _ in 0..frame_count {
+            self.render_frame()?;
         }
+        Ok(())
     }
-}
 
+    // 在两个队列之间转移资源所有权，stage/access默认最保守，可按需修改
+    pub fn queue_ownership_transfer(&self, src_flags: vk::QueueFlags, dst_flags: vk::QueueFlags)
+        -> Result<queue::QueueOwnershipTransfer>
+    {
+        let backend = self.backend.borrow();
+        Ok(queue::QueueOwnershipTransfer {
+            src_queue_family_index: backend.get_queue_family_index(src_flags)?,
+            dst_queue_family_index: backend.get_queue_family_index(dst_flags)?,
+            src_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
+            src_access_mask: vk::AccessFlags::MEMORY_WRITE,
+            dst_stage_mask: vk::PipelineStageFlags::ALL_COMMANDS,
+            dst_access_mask: vk::AccessFlags::MEMORY_READ | vk::AccessFlags::MEMORY_WRITE,
+        })
+    }
 
-pub trait RenderLoopAction {
-    fn acquire_next_image(& self) -> u32;
-    fn render_loop(&self);
-    fn render_frame(&self);
-    fn submit_frame(&mut self);
-}
+    // 回读离屏渲染目标并保存为png
+    pub fn capture_frame(&self, render_target: &render_target::RenderTarget, path: &str)
+        -> Result<()>
+    {
+        render_target.save_png(&self.backend.borrow(), self.cmd_pool, self.graphic_queue, path)
+    }
 
-pub trait RenderLoop {
-    fn render(&self, app_obj: &App);
-    fn update(&self, app_obj: &App, delta_time: f64);
-}
+    // 回读当前RenderLoop的输出图像（RGBA8）
+    pub fn read_back_frame(&self) -> Result<Option<(vk::Extent2D, Vec<u8>)>>
+    {
+        let render_loop_obj = self.render_loop_obj.borrow();
+        render_loop_obj.render_target()
+            .map(|render_target| {
+                render_target.read_back(&self.backend.borrow(), self.cmd_pool, self.graphic_queue)
+                    .map(|pixels| (render_target.extent, pixels))
+            })
+            .transpose()
+    }
 
-impl RenderLoopAction for App {
-    fn acquire_next_image(&self) -> u32
+    // 开始一帧：等待这一帧上一次的提交完成，获取swapchain图像，开始录制command buffer
+    // swapchain过期或窗口最小化时返回None，跳过这一帧
+    pub fn begin_frame(&self) -> Result<Option<frame::FrameContext>>
+    {
+        if self.resize_pending.get() && !self.recreate_swapchain()? {
+            return Ok(None);
+        }
+        let frame_index = self.frame_index.get();
+        let frame = &self.frames[frame_index];
+        frame.wait()?;
+
+        let (image_index, frame_buffer) = match &self.surface {
+            Some(surface) => {
+                let surface = surface.borrow();
+                match self.acquire_next_image(&surface, frame.image_available)? {
+                    Some(idx) => (Some(idx), surface.surface_frame_buffers[idx as usize]),
+                    None => return Ok(None),
+                }
+            },
+            None => (None, vk::Framebuffer::null()),
+        };
+
+        let cmd_buffer = frame.cmd_buffer;
+        unsafe {
+            let begin_info = vk::CommandBufferBeginInfo::builder()
+                .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+            self.backend.borrow().device.begin_command_buffer(cmd_buffer, &begin_info)?;
+        }
+        self.profiler.begin_frame(frame_index, cmd_buffer)?;
+        Ok(Some(frame::FrameContext {
+            frame_index,
+            cmd_buffer,
+            image_index,
+            frame_buffer,
+            extent: self.resolution(),
+        }))
+    }
+
+    // 结束录制并提交，获取了swapchain图像时等待image_available、signal render_complete，
+    // 再present同一张图像
+    pub fn end_frame(&self, frame_ctx: frame::FrameContext) -> Result<()>
+    {
+        let frame = &self.frames[frame_ctx.frame_index];
+        {
+            let backend = self.backend.borrow();
+            let device = &backend.device;
+            let (wait_semaphores, signal_semaphores) = match frame_ctx.image_index {
+                Some(_) => (vec![frame.image_available], vec![frame.render_complete]),
+                None => (vec![], vec![]),
+            };
+            let wait_stages = vec![vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT; wait_semaphores.len()];
+            let cmd_bufs = [frame_ctx.cmd_buffer];
+            let submit_info = vk::SubmitInfo::builder()
+                .wait_semaphores(&wait_semaphores)
+                .wait_dst_stage_mask(&wait_stages)
+                .command_buffers(&cmd_bufs)
+                .signal_semaphores(&signal_semaphores)
+                .build();
+            self.profiler.end_frame(frame_ctx.cmd_buffer);
+            unsafe {
+                device.end_command_buffer(frame_ctx.cmd_buffer)?;
+                device.reset_fences(&[frame.in_flight_fence])?;
+                device.queue_submit(self.graphic_queue, &[submit_info], frame.in_flight_fence)?;
+            }
+        }
+        self.frame_index.set((frame_ctx.frame_index + 1) % self.frames.len());
+
+        if let (Some(image_index), Some(surface)) = (frame_ctx.image_index, &self.surface) {
+            let surface = surface.borrow();
+            let wait_semaphores = [frame.render_complete];
+            let swapchains = [surface.swapchain_khr];
+            let image_indices = [image_index];
+            let present_info = vk::PresentInfoKHR::builder()
+                .wait_semaphores(&wait_semaphores)
+                .swapchains(&swapchains)
+                .image_indices(&image_indices);
+            let ret = unsafe {
+                surface.swapchain.queue_present(self.graphic_queue, &present_info)
+            };
+            match ret {
+                Ok(suboptimal) => if suboptimal {
+                    self.window_resize();
+                },
+                Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => self.window_resize(),
+                Err(err_code) => return Err(err_code.into()),
+            }
+        }
+        Ok(())
+    }
+
+    // 图像过期时返回None，suboptimal时图像仍然可用，这一帧照常渲染，下一帧重建
+    fn acquire_next_image(&self, surface: &surface::Surface, image_available: vk::Semaphore)
+        -> Result<Option<u32>>
     {
         let ret = unsafe {
-            self.surface.swapchain.acquire_next_image(
-                self.surface.swapchain_khr,
-                std::u64::MAX,
-                self.present_complete,
+            surface.swapchain.acquire_next_image(
+                surface.swapchain_khr,
+                u64::MAX,
+                image_available,
                 vk::Fence::null(),
             )
         };
         match ret {
-            Err(err_code) => match err_code {
-                vk::Result::ERROR_OUT_OF_DATE_KHR
-                | vk::Result::SUBOPTIMAL_KHR => {
+            Ok((idx, suboptimal)) => {
+                if suboptimal {
                     self.window_resize();
-                    std::u32::MAX
-                },
-                _ => {
-                    panic!("prepare_frame error {:?}", err_code);
                 }
+                Ok(Some(idx))
+            },
+            Err(vk::Result::ERROR_OUT_OF_DATE_KHR) => {
+                self.window_resize();
+                Ok(None)
             },
-            Ok((idx, _)) => idx
+            Err(err_code) => Err(err_code.into()),
         }
     }
 
-    fn render_loop(&self)
+    // 只做标记，实际重建在下一帧开始前进行，避免打断正在录制的command buffer
+    fn window_resize(&self)
     {
-        pub use winit::*;
-        self.events_loop.borrow_mut().run_forever(|event| {
-            // do render action
-            self.render_frame();
-
-            // solve window event
-            match event {
-                Event::WindowEvent { event, .. } => match event {
-                    WindowEvent::KeyboardInput { input, .. } => {
-                        if let Some(VirtualKeyCode::Escape) = input.virtual_keycode {
-                            ControlFlow::Break
-                        } else {
-                            ControlFlow::Continue
-                        }
-                    },
-                    WindowEvent::CloseRequested => ControlFlow::Break,
-                    _ => ControlFlow::Continue,
-                },
-                _ => ControlFlow::Continue,
-            }
-        })
+        self.resize_pending.set(true);
     }
 
-    fn render_frame(&self)
+    // 在两帧之间检查着色器改动，重建引用了这些着色器的PSO
+    // 编译或创建失败时打印错误，继续使用原来的pipeline
+    pub fn reload_shaders(&self) -> Result<()>
     {
-        let t_start = time::Instant::now().elapsed().as_micros();
-        self.render_loop_obj.render(self);
-        self.frame_count.set(self.frame_count.get() + 1u64);
-        let t_end = time::Instant::now().elapsed().as_micros();
-        let t_diff = (t_end - t_start) as f64;
-        self.frame_timer.set(t_diff / 1000.0);
-        self.render_loop_obj.update(self, self.frame_timer.take());
+        let mut watcher = match &self.shader_watcher {
+            Some(watcher) => watcher.borrow_mut(),
+            None => return Ok(()),
+        };
+        let mut render_loop_obj = self.render_loop_obj.borrow_mut();
+        let mut surface = self.surface.as_ref().map(|surface| surface.borrow_mut());
+        let mut pso_objs = render_loop_obj.pipeline_state_objects();
+        if let Some(surface) = surface.as_mut() {
+            pso_objs.push(&mut surface.surface_pso_obj);
+        }
+        for pso_obj in pso_objs.iter() {
+            watcher.watch(&pso_obj.pso_desc.vs_desc.path);
+            watcher.watch(&pso_obj.pso_desc.ps_desc.path);
+        }
+        let changed = watcher.poll();
+        if changed.is_empty() {
+            return Ok(());
+        }
+
+        let backend = self.backend.borrow().clone();
+        unsafe {
+            backend.device.device_wait_idle()?;
+        }
+        for pso_obj in pso_objs.into_iter() {
+            let desc = &pso_obj.pso_desc;
+            if !changed.contains(&desc.vs_desc.path) && !changed.contains(&desc.ps_desc.path) {
+                continue;
+            }
+            let name = desc.name.clone();
+            match utility::reload_pipeline_state_object(&backend, pso_obj) {
+                Ok(()) => log::info!("pipeline reloaded: {}", name),
+                // ShaderCompile的Display逐行列出编译器诊断
+                Err(e) => log::error!("pipeline {} reload failed, keeping the old one: {}", name, e),
+            }
+        }
+        Ok(())
     }
 
-    fn submit_frame(&mut self)
+    // 按窗口当前尺寸重建swapchain，并通知RenderLoop调整自己的attachment和viewport
+    // 窗口最小化时尺寸为0，保持pending状态，返回false跳过这一帧
+    fn recreate_swapchain(&self) -> Result<bool>
     {
-        let present_info_khr = vk::PresentInfoKHR::builder()
-            .swapchains(&[self.surface.swapchain_khr])
-            .wait_semaphores(&[self.render_complete])
+        let (window, surface) = match (&self.window, &self.surface) {
+            (Some(window), Some(surface)) => (window, surface),
+            _ => return Ok(true),
+        };
+        let extent = surface::Surface::window_extent(window);
+        if extent.width == 0 || extent.height == 0 {
+            return Ok(false);
+        }
+        surface.borrow_mut().recreate(extent)?;
+        let resolution = surface.borrow().surface_resolution;
+        self.render_loop_obj.borrow_mut().resize(self, resolution)?;
+        self.resize_pending.set(false);
+        Ok(true)
+    }
+}
+
 
-            .image_indices(&[0]) //  todo：应该设置有三个
-            .build();
+impl Drop for App {
+    fn drop(&mut self) {
         unsafe {
-            match self.surface.swapchain.queue_present(self.graphic_queue, &present_info_khr){
-                Ok(_) => {
-                    self.backend.borrow().device.queue_wait_idle(self.graphic_queue);
-                },
-                Err(err_code) => {
-                    match err_code {
-                        vk::Result::ERROR_OUT_OF_DATE_KHR => {
-                            self.window_resize();
-                        },
-                        _ => panic!("submit frame error => {:?}", err_code)
+            let device = &self.backend.borrow().device;
+            device.device_wait_idle();
+            device.destroy_command_pool(self.cmd_pool, None);
+            device.destroy_command_pool(self.compute_cmd_pool, None);
+            device.destroy_command_pool(self.transfer_cmd_pool, None);
+        }
+    }
+}
+
+
+pub trait RenderLoopAction {
+    // 出错时（例如device lost）停止循环并返回错误
+    fn render_loop(&self) -> Result<()>;
+    fn render_frame(&self) -> Result<()>;
+}
 
+pub trait RenderLoop {
+    // 在frame_ctx.cmd_buffer上录制这一帧的命令
+    fn render(&self, app_obj: &App, frame_ctx: &frame::FrameContext);
+    // delta_time单位为秒，已乘time_scale；固定步长模式下为步长
+    // 这一帧的输入通过app_obj.input()获取
+    fn update(&self, app_obj: &App, delta_time: f64);
+    // swapchain重建后调用，此时device已空闲，可以直接销毁重建尺寸相关的资源
+    fn resize(&mut self, _app_obj: &App, _extent: vk::Extent2D) -> Result<()> {
+        Ok(())
+    }
+    // 最终输出的离屏渲染目标，用于截图和图像回归测试
+    fn render_target(&self) -> Option<&render_target::RenderTarget> {
+        None
+    }
+    // 需要着色器热重载的PSO
+    fn pipeline_state_objects(&mut self) -> Vec<&mut pso::PipelineStateObject> {
+        vec![]
+    }
+}
+
+impl RenderLoopAction for App {
+    fn render_loop(&self) -> Result<()>
+    {
+        pub use winit::*;
+        let events_loop = self.events_loop.as_ref()
+            .expect("headless app has no events loop, use run_frames");
+        // 每帧先处理完所有事件再渲染，没有事件时也持续渲染
+        let mut running = true;
+        while running {
+            events_loop.borrow_mut().poll_events(|event| {
+                self.input.borrow_mut().handle_event(&event);
+                if let Event::WindowEvent { event, .. } = event {
+                    match event {
+                        WindowEvent::CloseRequested => running = false,
+                        WindowEvent::Resized(_) => self.window_resize(),
+                        _ => {},
                     }
                 }
+            });
+            if self.input.borrow().is_key_pressed(VirtualKeyCode::Escape) {
+                running = false;
             }
-        };
+            if running {
+                self.render_frame()?;
+            }
+            self.input.borrow_mut().end_frame();
+        }
+        Ok(())
     }
 
+    // 先update再render，固定步长模式下update按步长调用若干次
+    fn render_frame(&self) -> Result<()>
+    {
+        self.reload_shaders()?;
+        let (fixed_steps, fixed_step, scaled_delta) = {
+            let mut clock = self.clock.borrow_mut();
+            let fixed_steps = clock.tick();
+            (fixed_steps, clock.fixed_step, clock.scaled_delta)
+        };
+        match fixed_step {
+            Some(step) => for _ in 0..fixed_steps {
+                self.render_loop_obj.borrow().update(self, step);
+            },
+            None => self.render_loop_obj.borrow().update(self, scaled_delta),
+        }
+
+        let frame_ctx = match self.begin_frame()? {
+            Some(frame_ctx) => frame_ctx,
+            None => return Ok(()),
+        };
+        self.render_loop_obj.borrow().render(self, &frame_ctx);
+        self.end_frame(frame_ctx)
+    }
 }
 
 impl RenderLoop for DefaultRenderLoop {
-    fn render(&self, app_obj: &App)
+    fn render(&self, _app_obj: &App, _frame_ctx: &frame::FrameContext)
     {
         // self.prepare_frame();
         // let submit_info = vk::SubmitInfo::builder()
@@ -2,5 +2,19 @@ pub mod utility;
 pub mod pso;
 pub mod loader;
 pub mod ri;
+pub mod device_selector;
 pub mod buffer;
+pub mod queue;
+pub mod frame;
 pub mod surface;
+pub mod render_target;
+pub mod error;
+pub mod validation;
+pub mod debug;
+pub mod profiler;
+pub mod clock;
+pub mod input;
+pub mod camera;
+pub mod scene;
+pub mod descriptor;
+pub mod reflect;
\ No newline at end of file
@@ -3,6 +3,7 @@ use ash::vk;
 use ash::version::{DeviceV1_0, InstanceV1_0};
 use crate::base::ri;
 use crate::base::utility::find_memorytype_index;
+use crate::base::error::{Error, Result};
 use std::cell::RefCell;
 use std::ffi::c_void;
 
@@ -13,6 +14,8 @@ pub struct DeviceBuffer {
     pub device: ash::Device,
     size: u64,
     offset: u64,
+    // 每次分配的起始偏移按此对齐，uniform/storage buffer需要满足设备的offset对齐要求
+    alignment: u64,
     buffer_ptr: *mut c_void,
 }
 
@@ -20,54 +23,64 @@ impl DeviceBuffer {
     pub fn new(device: &ash::Device, device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
             buffer_ci: &vk::BufferCreateInfo,
             flags: vk::MemoryPropertyFlags)
-    -> DeviceBuffer
+    -> Result<DeviceBuffer>
     {
+        // 先构造空的DeviceBuffer，中途失败时由Drop释放已创建的对象
+        let mut buffer_obj = DeviceBuffer {
+            buffer: vk::Buffer::null(),
+            memory: vk::DeviceMemory::null(),
+            device: device.clone(),
+            size: buffer_ci.size,
+            offset: 0,
+            alignment: BUFFER_ALIGN,
+            buffer_ptr: std::ptr::null_mut(),
+        };
         // create buffer
-        let buffer;
         unsafe {
-            buffer = device.create_buffer(&buffer_ci, None)
-                .unwrap();
+            buffer_obj.buffer = device.create_buffer(buffer_ci, None)?;
         }
         // create memory
-        let memory;
         unsafe {
-            let memory_req = device.get_buffer_memory_requirements(buffer);
+            let memory_req = device.get_buffer_memory_requirements(buffer_obj.buffer);
 
             let memory_allocate_ci = vk::MemoryAllocateInfo {
                 allocation_size: memory_req.size,
                 memory_type_index: find_memorytype_index(
                     &memory_req,
-                    &device_memory_properties,
+                    device_memory_properties,
                     flags,
-                ).unwrap(),
+                ).ok_or(Error::NoSuitableMemoryType(flags))?,
                 ..Default::default()
             };
-            memory = device.allocate_memory(&memory_allocate_ci, None)
-                .unwrap();
+            buffer_obj.memory = device.allocate_memory(&memory_allocate_ci, None)?;
         }
         // bind memory to buffer
         unsafe {
-            // device.unmap_memory(memory);
-            device.bind_buffer_memory(buffer, memory, 0).unwrap();
+            device.bind_buffer_memory(buffer_obj.buffer, buffer_obj.memory, 0)?;
         }
 
-
-        let buffer_ptr = unsafe {
+        buffer_obj.buffer_ptr = unsafe {
             device.map_memory(
-                memory,
+                buffer_obj.memory,
                 0,
                 buffer_ci.size,
                 vk::MemoryMapFlags::empty()
-            ).unwrap()
+            )?
         };
 
-        DeviceBuffer {
-            buffer,
-            memory,
-            device: device.clone(),
-            size: buffer_ci.size,
-            offset: 0,
-            buffer_ptr,
+        Ok(buffer_obj)
+    }
+
+    pub fn set_name(&self, backend: &ri::Backend, name: &str)
+    {
+        backend.set_object_name(self.buffer, name);
+        backend.set_object_name(self.memory, &format!("{}_memory", name));
+    }
+
+    // 整块映射内存的只读视图，用于回读GPU写入的数据
+    pub fn as_bytes(&self) -> &[u8] {
+        unsafe {
+            std::slice::from_raw_parts(self.buffer_ptr as *const u8, self.size as usize)
         }
     }
 
@@ -75,20 +88,28 @@ impl DeviceBuffer {
         self.offset = 0;
     }
 
+    // alignment需要是2的幂，小于BUFFER_ALIGN时按BUFFER_ALIGN
+    pub fn set_alignment(&mut self, alignment: u64) {
+        assert!(alignment.is_power_of_two(), "alignment must be power of two");
+        self.alignment = std::cmp::max(alignment, BUFFER_ALIGN);
+    }
+
     pub fn allocate<T>(&mut self, size: u64)
         -> BufferSlice<T>
     {
-        let start = self.offset;
-        let new_offset = self.offset + (size + BUFFER_ALIGN - 1) / BUFFER_ALIGN * BUFFER_ALIGN;
+        let start = self.offset.next_multiple_of(self.alignment);
+        let new_offset = start + size.next_multiple_of(BUFFER_ALIGN);
         assert!(new_offset <= self.size, "buffer size is over");
         self.offset = new_offset;
         let truth_size = self.offset - start;
         // buffer_ptr已绑定memory，所以buf_ptr记录指针的偏移，方便后续写入数据。
         let slice = unsafe {
             let buf_ptr = self.buffer_ptr.wrapping_offset(start as isize);
+            // 元素按T自身的对齐排列，避免u16索引被填充成4字节间隔
+            let elem_align = std::cmp::min(std::mem::align_of::<T>() as u64, BUFFER_ALIGN);
             ash::util::Align::<T>::new(
                 buf_ptr,
-                BUFFER_ALIGN,
+                elem_align,
                 truth_size
             )
         };
@@ -104,7 +125,9 @@ impl DeviceBuffer {
 impl Drop for DeviceBuffer {
     fn drop(&mut self) {
         unsafe {
-            self.device.unmap_memory(self.memory);
+            if !self.buffer_ptr.is_null() {
+                self.device.unmap_memory(self.memory);
+            }
             self.buffer_ptr = std::ptr::null_mut();
             self.device.free_memory(self.memory, None);
             self.device.destroy_buffer(self.buffer, None);
@@ -134,7 +157,7 @@ impl BufferManagerSystem {
 
     pub fn new(backend: &ri::Backend, vertex_buf_size: vk::DeviceSize,
                index_buf_size: vk::DeviceSize, uniform_buf_size: vk::DeviceSize)
-        -> BufferManagerSystem
+        -> Result<BufferManagerSystem>
     {
         let device_memory_properties = unsafe {
             backend.instance.get_physical_device_memory_properties(backend.physical_device)
@@ -150,7 +173,7 @@ impl BufferManagerSystem {
             &device_memory_properties,
             &vertex_buffer_ci,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
+        )?;
         let index_buffer_ci = vk::BufferCreateInfo::builder()
             .size(index_buf_size)
             .usage(vk::BufferUsageFlags::INDEX_BUFFER)
@@ -161,26 +184,31 @@ impl BufferManagerSystem {
             &device_memory_properties,
             &index_buffer_ci,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
+        )?;
         let uniform_buffer_ci = vk::BufferCreateInfo::builder()
             .size(uniform_buf_size)
             .usage(vk::BufferUsageFlags::UNIFORM_BUFFER)
             .sharing_mode(vk::SharingMode::EXCLUSIVE)
             .build();
-        let uniform_buffer = DeviceBuffer::new(
+        let mut uniform_buffer = DeviceBuffer::new(
             &backend.device,
             &device_memory_properties,
             &uniform_buffer_ci,
             vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
-        );
-        BufferManagerSystem {
+        )?;
+        uniform_buffer.set_alignment(
+            backend.physical_device_info.properties.limits.min_uniform_buffer_offset_alignment);
+        vertex_buffer.set_name(backend, "vertex_buffer");
+        index_buffer.set_name(backend, "index_buffer");
+        uniform_buffer.set_name(backend, "uniform_buffer");
+        Ok(BufferManagerSystem {
             index_buf_size,
             index_buffer,
             vertex_buf_size,
             vertex_buffer,
             uniform_buf_size,
             uniform_buffer
-        }
+        })
     }
 
     pub fn allocate_vertex_buffer<T>(&mut self, size: u64)
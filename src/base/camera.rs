@@ -0,0 +1,317 @@
+use ash::vk;
+use directx_math::*;
+use winit::{MouseButton, VirtualKeyCode};
+use super::input::InputState;
+
+// 俯仰角限制，避免看向正上/正下方时view矩阵退化
+const MAX_PITCH: f32 = XM_PIDIV2 - 0.01;
+
+#[derive(Clone, Copy, Debug)]
+pub enum Projection {
+    // fov_y为弧度
+    Perspective { fov_y: f32, near: f32, far: f32 },
+    // height为可见区域高度，宽度按aspect计算
+    Orthographic { height: f32, near: f32, far: f32 },
+}
+
+// 右手坐标系，+Y向上，yaw = 0、pitch = 0时看向-Z
+// 投影矩阵输出Vulkan裁剪空间：y向下，深度范围[0, 1]
+#[derive(Clone, Copy, Debug)]
+pub struct Camera {
+    pub position: XMFLOAT3,
+    // 绕+Y轴，弧度
+    pub yaw: f32,
+    // 向上为正，弧度
+    pub pitch: f32,
+    pub projection: Projection,
+    // 宽/高，窗口尺寸变化时更新
+    pub aspect: f32,
+    // 近平面深度为1、远平面为0，PSO的depth compare和depth清除值见depth_compare_op/depth_clear_value
+    pub reversed_z: bool,
+}
+
+// 直接拷贝到uniform buffer，矩阵按行存储，shader中声明为mat4后用 m * v 计算
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CameraUniform {
+    pub view: XMFLOAT4X4,
+    pub projection: XMFLOAT4X4,
+    pub view_projection: XMFLOAT4X4,
+    pub position: XMFLOAT4,
+}
+
+impl Camera {
+    pub fn new(projection: Projection, aspect: f32) -> Self
+    {
+        Camera {
+            position: XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 },
+            yaw: 0.0,
+            pitch: 0.0,
+            projection,
+            aspect,
+            reversed_z: false,
+        }
+    }
+
+    pub fn perspective(fov_y: f32, aspect: f32, near: f32, far: f32) -> Self
+    {
+        Camera::new(Projection::Perspective { fov_y, near, far }, aspect)
+    }
+
+    pub fn orthographic(height: f32, aspect: f32, near: f32, far: f32) -> Self
+    {
+        Camera::new(Projection::Orthographic { height, near, far }, aspect)
+    }
+
+    // 填入PipelineStateObjectDescriptor::depth_compare_op
+    pub fn depth_compare_op(&self) -> vk::CompareOp
+    {
+        if self.reversed_z {
+            vk::CompareOp::GREATER_OR_EQUAL
+        } else {
+            vk::CompareOp::LESS_OR_EQUAL
+        }
+    }
+
+    // render pass开始时depth attachment的清除值，即最远处的深度
+    pub fn depth_clear_value(&self) -> f32
+    {
+        if self.reversed_z { 0.0 } else { 1.0 }
+    }
+
+    pub fn forward(&self) -> XMVECTOR
+    {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        XMVectorSet(-sin_yaw * cos_pitch, sin_pitch, -cos_yaw * cos_pitch, 0.0)
+    }
+
+    pub fn right(&self) -> XMVECTOR
+    {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        XMVectorSet(cos_yaw, 0.0, -sin_yaw, 0.0)
+    }
+
+    // 朝向target，position和target重合时保持原朝向
+    pub fn look_at(&mut self, target: XMFLOAT3)
+    {
+        let dx = target.x - self.position.x;
+        let dy = target.y - self.position.y;
+        let dz = target.z - self.position.z;
+        let horizontal = (dx * dx + dz * dz).sqrt();
+        if horizontal == 0.0 && dy == 0.0 {
+            return;
+        }
+        self.yaw = (-dx).atan2(-dz);
+        self.pitch = dy.atan2(horizontal).clamp(-MAX_PITCH, MAX_PITCH);
+    }
+
+    pub fn translate(&mut self, offset: XMVECTOR)
+    {
+        let position = XMVectorAdd(XMLoadFloat3(&self.position), offset);
+        XMStoreFloat3(&mut self.position, position);
+    }
+
+    pub fn view_matrix(&self) -> XMMATRIX
+    {
+        XMMatrixLookToRH(
+            XMLoadFloat3(&self.position),
+            self.forward(),
+            XMVectorSet(0.0, 1.0, 0.0, 0.0),
+        )
+    }
+
+    pub fn projection_matrix(&self) -> XMMATRIX
+    {
+        let projection = match self.projection {
+            Projection::Perspective { fov_y, near, far } => {
+                let (near, far) = if self.reversed_z { (far, near) } else { (near, far) };
+                XMMatrixPerspectiveFovRH(fov_y, self.aspect, near, far)
+            },
+            Projection::Orthographic { height, near, far } => {
+                let (near, far) = if self.reversed_z { (far, near) } else { (near, far) };
+                XMMatrixOrthographicRH(height * self.aspect, height, near, far)
+            },
+        };
+        // D3D裁剪空间y向上，Vulkan向下
+        XMMatrixMultiply(projection, &XMMatrixScaling(1.0, -1.0, 1.0))
+    }
+
+    pub fn view_projection_matrix(&self) -> XMMATRIX
+    {
+        XMMatrixMultiply(self.view_matrix(), &self.projection_matrix())
+    }
+
+    pub fn uniform(&self) -> CameraUniform
+    {
+        let mut uniform = CameraUniform::default();
+        let view = self.view_matrix();
+        let projection = self.projection_matrix();
+        XMStoreFloat4x4(&mut uniform.view, view);
+        XMStoreFloat4x4(&mut uniform.projection, projection);
+        XMStoreFloat4x4(&mut uniform.view_projection, XMMatrixMultiply(view, &projection));
+        uniform.position = XMFLOAT4 {
+            x: self.position.x,
+            y: self.position.y,
+            z: self.position.z,
+            w: 1.0,
+        };
+        uniform
+    }
+}
+
+// 第一人称漫游：WASD移动，Q/E下降/上升，按住右键拖动转向，Shift加速
+#[derive(Clone, Copy, Debug)]
+pub struct FlyController {
+    // 单位/秒
+    pub move_speed: f32,
+    // 弧度/鼠标位移单位
+    pub look_sensitivity: f32,
+    pub boost: f32,
+}
+
+impl Default for FlyController {
+    fn default() -> Self {
+        FlyController {
+            move_speed: 3.0,
+            look_sensitivity: 0.003,
+            boost: 4.0,
+        }
+    }
+}
+
+impl FlyController {
+    pub fn update(&self, camera: &mut Camera, input: &InputState, delta_time: f64)
+    {
+        if input.is_button_down(MouseButton::Right) {
+            let (dx, dy) = input.cursor_delta();
+            camera.yaw -= dx as f32 * self.look_sensitivity;
+            camera.pitch = (camera.pitch - dy as f32 * self.look_sensitivity)
+                .clamp(-MAX_PITCH, MAX_PITCH);
+        }
+
+        let axis = |positive: VirtualKeyCode, negative: VirtualKeyCode| {
+            input.is_key_down(positive) as i32 as f32 - input.is_key_down(negative) as i32 as f32
+        };
+        let forward = axis(VirtualKeyCode::W, VirtualKeyCode::S);
+        let right = axis(VirtualKeyCode::D, VirtualKeyCode::A);
+        let up = axis(VirtualKeyCode::E, VirtualKeyCode::Q);
+        if forward == 0.0 && right == 0.0 && up == 0.0 {
+            return;
+        }
+        let mut speed = self.move_speed * delta_time as f32;
+        if input.is_key_down(VirtualKeyCode::LShift) || input.is_key_down(VirtualKeyCode::RShift) {
+            speed *= self.boost;
+        }
+        let offset = XMVectorAdd(
+            XMVectorAdd(
+                XMVectorScale(camera.forward(), forward),
+                XMVectorScale(camera.right(), right),
+            ),
+            XMVectorSet(0.0, up, 0.0, 0.0),
+        );
+        camera.translate(XMVectorScale(XMVector3Normalize(offset), speed));
+    }
+}
+
+// 围绕target旋转：按住左键拖动旋转，滚轮缩放
+#[derive(Clone, Copy, Debug)]
+pub struct OrbitController {
+    pub target: XMFLOAT3,
+    pub distance: f32,
+    pub yaw: f32,
+    pub pitch: f32,
+    pub rotate_sensitivity: f32,
+    // 每格滚轮缩放的比例
+    pub zoom_speed: f32,
+    pub min_distance: f32,
+    pub max_distance: f32,
+}
+
+impl Default for OrbitController {
+    fn default() -> Self {
+        OrbitController {
+            target: XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 },
+            distance: 5.0,
+            yaw: 0.0,
+            pitch: 0.0,
+            rotate_sensitivity: 0.005,
+            zoom_speed: 0.1,
+            min_distance: 0.1,
+            max_distance: 1000.0,
+        }
+    }
+}
+
+impl OrbitController {
+    pub fn update(&mut self, camera: &mut Camera, input: &InputState, _delta_time: f64)
+    {
+        if input.is_button_down(MouseButton::Left) {
+            let (dx, dy) = input.cursor_delta();
+            self.yaw -= dx as f32 * self.rotate_sensitivity;
+            self.pitch = (self.pitch + dy as f32 * self.rotate_sensitivity)
+                .clamp(-MAX_PITCH, MAX_PITCH);
+        }
+        let (_, scroll) = input.scroll_delta();
+        if scroll != 0.0 {
+            self.distance = (self.distance * (1.0 - scroll as f32 * self.zoom_speed))
+                .max(self.min_distance)
+                .min(self.max_distance);
+        }
+        self.apply(camera);
+    }
+
+    // 按yaw/pitch/distance放置相机并看向target
+    pub fn apply(&self, camera: &mut Camera)
+    {
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+        camera.position = XMFLOAT3 {
+            x: self.target.x + self.distance * cos_pitch * sin_yaw,
+            y: self.target.y + self.distance * sin_pitch,
+            z: self.target.z + self.distance * cos_pitch * cos_yaw,
+        };
+        camera.yaw = self.yaw;
+        camera.pitch = -self.pitch;
+    }
+}
+
+
+#[test]
+fn test_camera_clip_space()
+{
+    let mut camera = Camera::perspective(XM_PIDIV2, 1.0, 0.1, 100.0);
+    camera.position = XMFLOAT3 { x: 0.0, y: 0.0, z: 5.0 };
+    camera.look_at(XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 });
+    assert!(camera.yaw.abs() < 1e-6 && camera.pitch.abs() < 1e-6);
+
+    let project = |camera: &Camera, x: f32, y: f32, z: f32| {
+        let mut clip = XMFLOAT3::default();
+        XMStoreFloat3(&mut clip, XMVector3TransformCoord(
+            XMVectorSet(x, y, z, 1.0), camera.view_projection_matrix()));
+        clip
+    };
+    // 上方的点在Vulkan裁剪空间中y为负
+    let clip = project(&camera, 0.0, 1.0, 0.0);
+    assert!(clip.y < 0.0);
+    let near = project(&camera, 0.0, 0.0, 4.9);
+    let far = project(&camera, 0.0, 0.0, -95.0);
+    assert!(near.z.abs() < 1e-4 && (far.z - 1.0).abs() < 1e-4);
+
+    camera.reversed_z = true;
+    let near = project(&camera, 0.0, 0.0, 4.9);
+    let far = project(&camera, 0.0, 0.0, -95.0);
+    assert!((near.z - 1.0).abs() < 1e-4 && far.z.abs() < 1e-4);
+    assert_eq!(camera.depth_compare_op(), vk::CompareOp::GREATER_OR_EQUAL);
+    assert_eq!(camera.depth_clear_value(), far.z.round());
+
+    // orbit相机始终看向target
+    let orbit = OrbitController {
+        yaw: 0.7,
+        pitch: 0.3,
+        ..Default::default()
+    };
+    orbit.apply(&mut camera);
+    let center = project(&camera, 0.0, 0.0, 0.0);
+    assert!(center.x.abs() < 1e-4 && center.y.abs() < 1e-4);
+}
@@ -0,0 +1,110 @@
+use std::time::Instant;
+
+// 两帧间隔的上限（秒），断点调试或窗口拖动后不会一次补太多步
+const MAX_DELTA: f64 = 0.25;
+// FPS指数平滑系数
+const FPS_SMOOTHING: f64 = 0.1;
+
+// 帧时钟，时间单位都是秒
+pub struct FrameClock {
+    last_tick: Option<Instant>,
+    // 上一帧到这一帧的实际时间
+    pub delta: f64,
+    // 乘以time_scale后的时间，update使用
+    pub scaled_delta: f64,
+    // 缩放后的累计时间
+    pub elapsed: f64,
+    pub frame_count: u64,
+    pub fps: f64,
+    // 0为暂停，小于1为慢动作
+    pub time_scale: f64,
+    // 固定步长，None表示每帧按scaled_delta调用一次update
+    pub fixed_step: Option<f64>,
+    accumulator: f64,
+}
+
+impl FrameClock {
+    pub fn new(fixed_step: Option<f64>) -> Self
+    {
+        FrameClock {
+            last_tick: None,
+            delta: 0.0,
+            scaled_delta: 0.0,
+            elapsed: 0.0,
+            frame_count: 0,
+            fps: 0.0,
+            time_scale: 1.0,
+            fixed_step: fixed_step.filter(|&step| step > 0.0),
+            accumulator: 0.0,
+        }
+    }
+
+    // 每帧开始时调用，返回这一帧需要执行的固定步数
+    pub fn tick(&mut self) -> u32
+    {
+        let now = Instant::now();
+        let delta = match self.last_tick {
+            Some(last_tick) => now.duration_since(last_tick).as_secs_f64(),
+            None => 0.0,
+        };
+        self.last_tick = Some(now);
+        self.advance(delta)
+    }
+
+    // 按给定的间隔推进，tick和测试共用
+    pub fn advance(&mut self, delta: f64) -> u32
+    {
+        let delta = delta.clamp(0.0, MAX_DELTA);
+        self.delta = delta;
+        self.scaled_delta = delta * self.time_scale;
+        self.elapsed += self.scaled_delta;
+        self.frame_count += 1;
+        if delta > 0.0 {
+            self.fps = if self.fps == 0.0 {
+                1.0 / delta
+            } else {
+                self.fps * (1.0 - FPS_SMOOTHING) + FPS_SMOOTHING / delta
+            };
+        }
+        match self.fixed_step {
+            Some(step) => {
+                self.accumulator += self.scaled_delta;
+                let steps = (self.accumulator / step).floor();
+                self.accumulator -= steps * step;
+                steps as u32
+            },
+            None => 0,
+        }
+    }
+
+    // 固定步长模式下上一步之后剩余时间占一步的比例，渲染时用于插值
+    pub fn alpha(&self) -> f64
+    {
+        match self.fixed_step {
+            Some(step) => self.accumulator / step,
+            None => 0.0,
+        }
+    }
+}
+
+
+#[test]
+fn test_frame_clock()
+{
+    let mut clock = FrameClock::new(Some(0.01));
+    assert_eq!(clock.advance(0.025), 2);
+    assert!((clock.alpha() - 0.5).abs() < 1e-6);
+    assert_eq!(clock.advance(0.005), 1);
+    assert!((clock.fps - (0.9 * 40.0 + 0.1 * 200.0)).abs() < 1e-6);
+
+    // 暂停时不推进逻辑时间
+    clock.time_scale = 0.0;
+    assert_eq!(clock.advance(0.1), 0);
+    assert!((clock.elapsed - 0.03).abs() < 1e-6);
+
+    // 过长的间隔会被截断
+    clock.time_scale = 1.0;
+    clock.advance(10.0);
+    assert_eq!(clock.delta, MAX_DELTA);
+    assert_eq!(clock.frame_count, 4);
+}
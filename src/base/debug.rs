@@ -0,0 +1,64 @@
+use ash::extensions::ext;
+use ash::vk;
+use std::ffi::CString;
+
+// 给Vulkan对象设置调试名，验证层消息和抓帧工具中显示名字而不是句柄
+// 调试名只用于调试，失败时忽略
+pub fn set_object_name<T: vk::Handle>(debug_utils: &ext::DebugUtils, device: vk::Device,
+                                      handle: T, name: &str)
+{
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return,
+    };
+    let name_info = vk::DebugUtilsObjectNameInfoEXT::builder()
+        .object_type(T::TYPE)
+        .object_handle(handle.as_raw())
+        .object_name(&name);
+    unsafe {
+        let _ = debug_utils.debug_utils_set_object_name(device, &name_info);
+    }
+}
+
+// command buffer上的一段标签区域，离开作用域时结束
+pub struct CmdLabel<'a> {
+    debug_utils: &'a ext::DebugUtils,
+    cmd_buf: vk::CommandBuffer,
+}
+
+impl<'a> CmdLabel<'a> {
+    pub fn new(debug_utils: &'a ext::DebugUtils, cmd_buf: vk::CommandBuffer,
+               name: &str, color: [f32; 4]) -> Self
+    {
+        let name = CString::new(name).unwrap_or_default();
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(&name)
+            .color(color);
+        unsafe {
+            debug_utils.cmd_begin_debug_utils_label(cmd_buf, &label);
+        }
+        CmdLabel {
+            debug_utils,
+            cmd_buf,
+        }
+    }
+
+    // 在当前区域内插入一个单点标签
+    pub fn insert(&self, name: &str)
+    {
+        let name = CString::new(name).unwrap_or_default();
+        let label = vk::DebugUtilsLabelEXT::builder()
+            .label_name(&name);
+        unsafe {
+            self.debug_utils.cmd_insert_debug_utils_label(self.cmd_buf, &label);
+        }
+    }
+}
+
+impl<'a> Drop for CmdLabel<'a> {
+    fn drop(&mut self) {
+        unsafe {
+            self.debug_utils.cmd_end_debug_utils_label(self.cmd_buf);
+        }
+    }
+}
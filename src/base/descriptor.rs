@@ -0,0 +1,261 @@
+use ash::vk;
+use ash::version::*;
+use super::buffer::BufferSlice;
+use super::error::Result;
+
+// 每个set平均需要的各类descriptor数量，pool按 容量 * 比例 分配
+const DEFAULT_POOL_RATIOS: [(vk::DescriptorType, f32); 11] = [
+    (vk::DescriptorType::SAMPLER, 0.5),
+    (vk::DescriptorType::COMBINED_IMAGE_SAMPLER, 4.0),
+    (vk::DescriptorType::SAMPLED_IMAGE, 4.0),
+    (vk::DescriptorType::STORAGE_IMAGE, 1.0),
+    (vk::DescriptorType::UNIFORM_TEXEL_BUFFER, 1.0),
+    (vk::DescriptorType::STORAGE_TEXEL_BUFFER, 1.0),
+    (vk::DescriptorType::UNIFORM_BUFFER, 2.0),
+    (vk::DescriptorType::STORAGE_BUFFER, 2.0),
+    (vk::DescriptorType::UNIFORM_BUFFER_DYNAMIC, 1.0),
+    (vk::DescriptorType::STORAGE_BUFFER_DYNAMIC, 1.0),
+    (vk::DescriptorType::INPUT_ATTACHMENT, 0.5),
+];
+// 第一个pool能分配的set数量，之后每个新pool翻倍
+const INITIAL_SETS_PER_POOL: u32 = 64;
+const MAX_SETS_PER_POOL: u32 = 4096;
+
+// 按需创建descriptor pool，当前pool用完（或碎片化）时换一个新的继续分配
+pub struct DescriptorAllocator {
+    pool_ratios: Vec<(vk::DescriptorType, f32)>,
+    sets_per_pool: u32,
+    current_pool: vk::DescriptorPool,
+    used_pools: Vec<vk::DescriptorPool>,
+    // reset后可以复用的pool
+    free_pools: Vec<vk::DescriptorPool>,
+    device: ash::Device,
+}
+
+impl DescriptorAllocator {
+    pub fn new(device: &ash::Device) -> Self
+    {
+        DescriptorAllocator::with_pool_ratios(device, &DEFAULT_POOL_RATIOS)
+    }
+
+    pub fn with_pool_ratios(device: &ash::Device, pool_ratios: &[(vk::DescriptorType, f32)]) -> Self
+    {
+        DescriptorAllocator {
+            pool_ratios: pool_ratios.to_vec(),
+            sets_per_pool: INITIAL_SETS_PER_POOL,
+            current_pool: vk::DescriptorPool::null(),
+            used_pools: vec![],
+            free_pools: vec![],
+            device: device.clone(),
+        }
+    }
+
+    pub fn allocate(&mut self, layout: vk::DescriptorSetLayout) -> Result<vk::DescriptorSet>
+    {
+        if self.current_pool == vk::DescriptorPool::null() {
+            self.current_pool = self.grab_pool()?;
+        }
+        match self.try_allocate(layout) {
+            Err(vk::Result::ERROR_OUT_OF_POOL_MEMORY) | Err(vk::Result::ERROR_FRAGMENTED_POOL) => {
+                self.current_pool = self.grab_pool()?;
+                Ok(self.try_allocate(layout)?)
+            },
+            result => Ok(result?),
+        }
+    }
+
+    // 重置所有pool，之前分配的set全部失效，需要确保GPU不再使用它们
+    pub fn reset(&mut self) -> Result<()>
+    {
+        for &pool in self.used_pools.iter() {
+            unsafe {
+                self.device.reset_descriptor_pool(pool, vk::DescriptorPoolResetFlags::empty())?;
+            }
+        }
+        self.free_pools.append(&mut self.used_pools);
+        self.current_pool = vk::DescriptorPool::null();
+        Ok(())
+    }
+
+    fn try_allocate(&self, layout: vk::DescriptorSetLayout) -> ash::prelude::VkResult<vk::DescriptorSet>
+    {
+        let set_layouts = [layout];
+        let allocate_info = vk::DescriptorSetAllocateInfo::builder()
+            .descriptor_pool(self.current_pool)
+            .set_layouts(&set_layouts);
+        unsafe {
+            self.device.allocate_descriptor_sets(&allocate_info).map(|sets| sets[0])
+        }
+    }
+
+    fn grab_pool(&mut self) -> Result<vk::DescriptorPool>
+    {
+        let pool = match self.free_pools.pop() {
+            Some(pool) => pool,
+            None => {
+                let pool_sizes = pool_sizes(&self.pool_ratios, self.sets_per_pool);
+                let pool_ci = vk::DescriptorPoolCreateInfo::builder()
+                    .pool_sizes(&pool_sizes)
+                    .max_sets(self.sets_per_pool);
+                let pool = unsafe {
+                    self.device.create_descriptor_pool(&pool_ci, None)?
+                };
+                self.sets_per_pool = std::cmp::min(self.sets_per_pool * 2, MAX_SETS_PER_POOL);
+                pool
+            },
+        };
+        self.used_pools.push(pool);
+        Ok(pool)
+    }
+}
+
+impl Drop for DescriptorAllocator {
+    fn drop(&mut self) {
+        unsafe {
+            for &pool in self.used_pools.iter().chain(self.free_pools.iter()) {
+                self.device.destroy_descriptor_pool(pool, None);
+            }
+        }
+    }
+}
+
+// 每种类型至少1个
+pub fn pool_sizes(pool_ratios: &[(vk::DescriptorType, f32)], set_count: u32)
+    -> Vec<vk::DescriptorPoolSize>
+{
+    pool_ratios
+        .iter()
+        .map(|&(ty, ratio)| vk::DescriptorPoolSize {
+            ty,
+            descriptor_count: std::cmp::max(1, (ratio * set_count as f32) as u32),
+        })
+        .collect()
+}
+
+enum DescriptorInfo {
+    Buffer(vk::DescriptorBufferInfo),
+    Image(vk::DescriptorImageInfo),
+}
+
+// 收集对一个set的写入，update时一次提交
+// let set = app_obj.descriptor_allocator.allocate(pso_obj.descriptor_set_layouts[0])?;
+// DescriptorSetWriter::new(set)
+//     .uniform_buffer(0, &uniform)
+//     .combined_image_sampler(1, sampler, view, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+//     .update(device);
+pub struct DescriptorSetWriter {
+    descriptor_set: vk::DescriptorSet,
+    writes: Vec<(u32, vk::DescriptorType, DescriptorInfo)>,
+}
+
+impl DescriptorSetWriter {
+    pub fn new(descriptor_set: vk::DescriptorSet) -> Self
+    {
+        DescriptorSetWriter {
+            descriptor_set,
+            writes: vec![],
+        }
+    }
+
+    pub fn buffer(mut self, binding: u32, descriptor_type: vk::DescriptorType, buffer: vk::Buffer,
+                  offset: vk::DeviceSize, range: vk::DeviceSize) -> Self
+    {
+        let info = vk::DescriptorBufferInfo {
+            buffer,
+            offset,
+            range,
+        };
+        self.writes.push((binding, descriptor_type, DescriptorInfo::Buffer(info)));
+        self
+    }
+
+    pub fn uniform_buffer<T>(self, binding: u32, slice: &BufferSlice<T>) -> Self
+    {
+        self.buffer(binding, vk::DescriptorType::UNIFORM_BUFFER, slice.buffer, slice.offset, slice.size)
+    }
+
+    pub fn storage_buffer<T>(self, binding: u32, slice: &BufferSlice<T>) -> Self
+    {
+        self.buffer(binding, vk::DescriptorType::STORAGE_BUFFER, slice.buffer, slice.offset, slice.size)
+    }
+
+    pub fn image(mut self, binding: u32, descriptor_type: vk::DescriptorType, sampler: vk::Sampler,
+                 image_view: vk::ImageView, image_layout: vk::ImageLayout) -> Self
+    {
+        let info = vk::DescriptorImageInfo {
+            sampler,
+            image_view,
+            image_layout,
+        };
+        self.writes.push((binding, descriptor_type, DescriptorInfo::Image(info)));
+        self
+    }
+
+    pub fn sampled_image(self, binding: u32, image_view: vk::ImageView, image_layout: vk::ImageLayout)
+        -> Self
+    {
+        self.image(binding, vk::DescriptorType::SAMPLED_IMAGE, vk::Sampler::null(), image_view, image_layout)
+    }
+
+    pub fn storage_image(self, binding: u32, image_view: vk::ImageView) -> Self
+    {
+        self.image(binding, vk::DescriptorType::STORAGE_IMAGE, vk::Sampler::null(), image_view,
+                   vk::ImageLayout::GENERAL)
+    }
+
+    pub fn sampler(self, binding: u32, sampler: vk::Sampler) -> Self
+    {
+        self.image(binding, vk::DescriptorType::SAMPLER, sampler, vk::ImageView::null(),
+                   vk::ImageLayout::UNDEFINED)
+    }
+
+    pub fn combined_image_sampler(self, binding: u32, sampler: vk::Sampler, image_view: vk::ImageView,
+                                  image_layout: vk::ImageLayout) -> Self
+    {
+        self.image(binding, vk::DescriptorType::COMBINED_IMAGE_SAMPLER, sampler, image_view, image_layout)
+    }
+
+    // set不能正在被提交的command buffer使用
+    pub fn update(self, device: &ash::Device)
+    {
+        let writes = self.writes
+            .iter()
+            .map(|(binding, descriptor_type, info)| {
+                let write = vk::WriteDescriptorSet {
+                    dst_set: self.descriptor_set,
+                    dst_binding: *binding,
+                    descriptor_count: 1,
+                    descriptor_type: *descriptor_type,
+                    ..Default::default()
+                };
+                match info {
+                    DescriptorInfo::Buffer(info) => vk::WriteDescriptorSet {
+                        p_buffer_info: info,
+                        ..write
+                    },
+                    DescriptorInfo::Image(info) => vk::WriteDescriptorSet {
+                        p_image_info: info,
+                        ..write
+                    },
+                }
+            })
+            .collect::<Vec<vk::WriteDescriptorSet>>();
+        unsafe {
+            device.update_descriptor_sets(&writes, &[]);
+        }
+    }
+}
+
+
+#[test]
+fn test_pool_sizes()
+{
+    let ratios = [
+        (vk::DescriptorType::UNIFORM_BUFFER, 2.0),
+        (vk::DescriptorType::SAMPLER, 0.001),
+    ];
+    let sizes = pool_sizes(&ratios, 64);
+    assert_eq!(sizes[0].ty, vk::DescriptorType::UNIFORM_BUFFER);
+    assert_eq!(sizes[0].descriptor_count, 128);
+    assert_eq!(sizes[1].descriptor_count, 1);
+}
@@ -0,0 +1,269 @@
+use ash::extensions::khr;
+use ash::version::*;
+use ash::vk;
+use std::ffi::{CStr, CString};
+use super::error::{Error, Result};
+
+// 强制选择某个设备，Auto表示按评分选择
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum DevicePreference {
+    #[default]
+    Auto,
+    Index(usize),
+    Name(String),
+    Uuid([u8; vk::UUID_SIZE]),
+}
+
+#[derive(Clone, Debug)]
+pub struct DeviceRequirements {
+    pub extensions: Vec<CString>,
+    pub features: vk::PhysicalDeviceFeatures,
+    // 至少有一个queue family同时支持这些能力
+    pub queue_flags: vk::QueueFlags,
+    pub min_image_dimension_2d: u32,
+    pub min_push_constants_size: u32,
+    pub min_bound_descriptor_sets: u32,
+}
+
+impl Default for DeviceRequirements {
+    fn default() -> Self {
+        DeviceRequirements {
+            extensions: vec![],
+            features: vk::PhysicalDeviceFeatures {
+                shader_clip_distance: 1,
+                ..Default::default()
+            },
+            queue_flags: vk::QueueFlags::GRAPHICS,
+            min_image_dimension_2d: 0,
+            min_push_constants_size: 0,
+            min_bound_descriptor_sets: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct DeviceSelector {
+    pub requirements: DeviceRequirements,
+    pub preference: DevicePreference,
+}
+
+// 枚举到的候选设备
+#[derive(Clone)]
+pub struct PhysicalDeviceInfo {
+    pub physical_device: vk::PhysicalDevice,
+    pub name: String,
+    pub properties: vk::PhysicalDeviceProperties,
+    pub features: vk::PhysicalDeviceFeatures,
+    pub queue_families: Vec<vk::QueueFamilyProperties>,
+    pub extensions: Vec<CString>,
+    // VkPhysicalDeviceIDProperties::deviceUUID，instance或设备不支持Vulkan 1.1时为None
+    pub device_uuid: Option<[u8; vk::UUID_SIZE]>,
+    // 不满足需求的设备为None，原因记录在unsuitable_reason
+    pub score: Option<u32>,
+    pub unsuitable_reason: Option<String>,
+}
+
+impl std::fmt::Debug for PhysicalDeviceInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("PhysicalDeviceInfo")
+            .field("name", &self.name)
+            .field("device_type", &self.properties.device_type)
+            .field("score", &self.score)
+            .field("unsuitable_reason", &self.unsuitable_reason)
+            .finish()
+    }
+}
+
+impl PhysicalDeviceInfo {
+    pub fn supports_extension(&self, name: &CStr) -> bool {
+        self.extensions.iter().any(|ext| ext.as_c_str() == name)
+    }
+}
+
+// 设备类型评分：独显 > 集显 > 虚拟 > CPU
+pub fn device_type_score(device_type: vk::PhysicalDeviceType) -> u32 {
+    match device_type {
+        vk::PhysicalDeviceType::DISCRETE_GPU => 1000,
+        vk::PhysicalDeviceType::INTEGRATED_GPU => 100,
+        vk::PhysicalDeviceType::VIRTUAL_GPU => 10,
+        vk::PhysicalDeviceType::CPU => 1,
+        _ => 0,
+    }
+}
+
+// 检查supported是否包含required中开启的所有feature
+pub fn supports_features(supported: &vk::PhysicalDeviceFeatures,
+                         required: &vk::PhysicalDeviceFeatures) -> bool {
+    // PhysicalDeviceFeatures全部由Bool32组成，按数组逐项比较
+    let count = std::mem::size_of::<vk::PhysicalDeviceFeatures>() / std::mem::size_of::<vk::Bool32>();
+    let (supported, required) = unsafe {
+        (
+            std::slice::from_raw_parts(supported as *const _ as *const vk::Bool32, count),
+            std::slice::from_raw_parts(required as *const _ as *const vk::Bool32, count),
+        )
+    };
+    supported.iter()
+        .zip(required.iter())
+        .all(|(&s, &r)| r == vk::FALSE || s == vk::TRUE)
+}
+
+// vkGetPhysicalDeviceProperties2需要instance和设备都支持Vulkan 1.1
+fn device_uuid(instance: &ash::Instance, instance_version: u32, physical_device: vk::PhysicalDevice,
+               properties: &vk::PhysicalDeviceProperties)
+    -> Option<[u8; vk::UUID_SIZE]>
+{
+    let version_1_1 = vk::make_version(1, 1, 0);
+    if instance_version < version_1_1 || properties.api_version < version_1_1 {
+        return None;
+    }
+    let mut id_properties = vk::PhysicalDeviceIDProperties::default();
+    let mut properties2 = vk::PhysicalDeviceProperties2::builder()
+        .push_next(&mut id_properties)
+        .build();
+    unsafe {
+        instance.get_physical_device_properties2(physical_device, &mut properties2);
+    }
+    Some(id_properties.device_uuid)
+}
+
+impl DeviceSelector {
+    // 枚举所有设备并评分，surface不为None时还要求queue支持present
+    // instance_version为创建instance时的api_version，决定能否查询device UUID
+    pub fn enumerate(&self, instance: &ash::Instance, instance_version: u32,
+                     surface: Option<(&khr::Surface, vk::SurfaceKHR)>)
+        -> Result<Vec<PhysicalDeviceInfo>>
+    {
+        let physical_devices = unsafe {
+            instance.enumerate_physical_devices()?
+        };
+        let mut infos = Vec::with_capacity(physical_devices.len());
+        for physical_device in physical_devices {
+            let (properties, features, queue_families, extension_props) = unsafe {
+                (
+                    instance.get_physical_device_properties(physical_device),
+                    instance.get_physical_device_features(physical_device),
+                    instance.get_physical_device_queue_family_properties(physical_device),
+                    instance.enumerate_device_extension_properties(physical_device)?,
+                )
+            };
+            let name = unsafe {
+                CStr::from_ptr(properties.device_name.as_ptr())
+                    .to_string_lossy()
+                    .into_owned()
+            };
+            let extensions = extension_props.iter()
+                .map(|prop| unsafe {
+                    CStr::from_ptr(prop.extension_name.as_ptr()).to_owned()
+                })
+                .collect::<Vec<CString>>();
+            let device_uuid = device_uuid(instance, instance_version, physical_device, &properties);
+            let mut info = PhysicalDeviceInfo {
+                physical_device,
+                name,
+                properties,
+                features,
+                queue_families,
+                extensions,
+                device_uuid,
+                score: None,
+                unsuitable_reason: None,
+            };
+            match self.check_requirements(&info, surface)? {
+                None => info.score = Some(device_type_score(properties.device_type)),
+                Some(reason) => info.unsuitable_reason = Some(reason),
+            }
+            infos.push(info);
+        }
+        Ok(infos)
+    }
+
+    pub fn select(&self, instance: &ash::Instance, instance_version: u32,
+                  surface: Option<(&khr::Surface, vk::SurfaceKHR)>)
+        -> Result<PhysicalDeviceInfo>
+    {
+        let infos = self.enumerate(instance, instance_version, surface)?;
+        let selected = match &self.preference {
+            DevicePreference::Auto => {
+                // 分数相同时保持枚举顺序
+                infos.into_iter()
+                    .filter(|info| info.score.is_some())
+                    .fold(None, |best: Option<PhysicalDeviceInfo>, info| match best {
+                        Some(best) if best.score >= info.score => Some(best),
+                        _ => Some(info),
+                    })
+            },
+            DevicePreference::Index(idx) => infos.into_iter().nth(*idx),
+            DevicePreference::Name(name) => infos.into_iter().find(|info| &info.name == name),
+            DevicePreference::Uuid(uuid) =>
+                infos.into_iter().find(|info| info.device_uuid.as_ref() == Some(uuid)),
+        };
+        match selected {
+            Some(info) if info.score.is_some() => Ok(info),
+            _ => Err(Error::NoPhysicalDevice),
+        }
+    }
+
+    // 满足需求返回None，否则返回原因
+    fn check_requirements(&self, info: &PhysicalDeviceInfo,
+                          surface: Option<(&khr::Surface, vk::SurfaceKHR)>)
+        -> Result<Option<String>>
+    {
+        let req = &self.requirements;
+        if let Some(ext) = req.extensions.iter().find(|ext| !info.supports_extension(ext)) {
+            return Ok(Some(format!("missing extension {:?}", ext)));
+        }
+        if !supports_features(&info.features, &req.features) {
+            return Ok(Some("missing required features".to_string()));
+        }
+        let limits = &info.properties.limits;
+        if limits.max_image_dimension2_d < req.min_image_dimension_2d
+            || limits.max_push_constants_size < req.min_push_constants_size
+            || limits.max_bound_descriptor_sets < req.min_bound_descriptor_sets {
+            return Ok(Some("limits too low".to_string()));
+        }
+        let mut has_queue = false;
+        for (index, family) in info.queue_families.iter().enumerate() {
+            if !family.queue_flags.contains(req.queue_flags) {
+                continue;
+            }
+            let supports_present = match surface {
+                Some((surface_loader, surface_khr)) => unsafe {
+                    surface_loader.get_physical_device_surface_support(
+                        info.physical_device, index as u32, surface_khr)?
+                },
+                None => true,
+            };
+            if supports_present {
+                has_queue = true;
+                break;
+            }
+        }
+        if !has_queue {
+            return Ok(Some(format!("no queue family supports {:?}", req.queue_flags)));
+        }
+        Ok(None)
+    }
+}
+
+
+#[test]
+fn test_supports_features()
+{
+    let supported = vk::PhysicalDeviceFeatures {
+        shader_clip_distance: 1,
+        sampler_anisotropy: 1,
+        ..Default::default()
+    };
+    let required = vk::PhysicalDeviceFeatures {
+        sampler_anisotropy: 1,
+        ..Default::default()
+    };
+    assert!(supports_features(&supported, &required));
+    let required = vk::PhysicalDeviceFeatures {
+        geometry_shader: 1,
+        ..Default::default()
+    };
+    assert!(!supports_features(&supported, &required));
+    assert!(device_type_score(vk::PhysicalDeviceType::DISCRETE_GPU)
+        > device_type_score(vk::PhysicalDeviceType::INTEGRATED_GPU));
+}
@@ -0,0 +1,104 @@
+use ash::vk;
+use std::fmt;
+use std::io;
+use super::loader::ShaderDiagnostic;
+
+#[derive(Debug)]
+pub enum Error {
+    // 加载Vulkan动态库失败
+    Loading(ash::LoadingError),
+    // 创建VkInstance失败
+    Instance(ash::InstanceError),
+    Window(winit::CreationError),
+    Vulkan(vk::Result),
+    // swapchain需要重建（窗口尺寸变化等）
+    SwapchainOutOfDate,
+    // 编译器给出的错误和警告
+    ShaderCompile { path: String, diagnostics: Vec<ShaderDiagnostic> },
+    // SPIR-V无法解析
+    ShaderReflect { path: String, message: String },
+    // PSO描述与着色器的输入、资源不一致，name为PSO名
+    PipelineLayoutMismatch { name: String, message: String },
+    // 更新的push constant范围与PSO声明的range不符，name为PSO名
+    PushConstantRange { name: String, message: String },
+    Io(io::Error),
+    NoPhysicalDevice,
+    NoSuitableMemoryType(vk::MemoryPropertyFlags),
+    // 渲染目标的color格式不支持回读
+    UnsupportedFormat(vk::Format),
+    // PSO没有color attachment，无法创建渲染目标，参数为PSO名
+    NoColorAttachment(String),
+    NoQueueFamily(vk::QueueFlags),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Loading(e) => write!(f, "failed to load vulkan library: {}", e),
+            Error::Instance(e) => write!(f, "failed to create instance: {}", e),
+            Error::Window(e) => write!(f, "failed to create window: {}", e),
+            Error::Vulkan(code) => write!(f, "vulkan error: {:?}", code),
+            Error::SwapchainOutOfDate => write!(f, "swapchain out of date"),
+            Error::ShaderCompile { path, diagnostics } => {
+                write!(f, "failed to compile shader {}", path)?;
+                for diagnostic in diagnostics.iter() {
+                    write!(f, "\n  {}", diagnostic)?;
+                }
+                Ok(())
+            },
+            Error::ShaderReflect { path, message } =>
+                write!(f, "failed to reflect shader {}: {}", path, message),
+            Error::PipelineLayoutMismatch { name, message } =>
+                write!(f, "pipeline {} does not match its shaders: {}", name, message),
+            Error::PushConstantRange { name, message } =>
+                write!(f, "invalid push constant update for pipeline {}: {}", name, message),
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::NoPhysicalDevice => write!(f, "no suitable physical device"),
+            Error::NoSuitableMemoryType(flags) =>
+                write!(f, "no suitable memory type for {:?}", flags),
+            Error::UnsupportedFormat(format) =>
+                write!(f, "unsupported render target format {:?}", format),
+            Error::NoColorAttachment(name) =>
+                write!(f, "pipeline {} has no color attachment for a render target", name),
+            Error::NoQueueFamily(flags) =>
+                write!(f, "no queue family supports {:?}", flags),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<vk::Result> for Error {
+    fn from(code: vk::Result) -> Self {
+        match code {
+            vk::Result::ERROR_OUT_OF_DATE_KHR => Error::SwapchainOutOfDate,
+            _ => Error::Vulkan(code),
+        }
+    }
+}
+
+impl From<ash::LoadingError> for Error {
+    fn from(e: ash::LoadingError) -> Self {
+        Error::Loading(e)
+    }
+}
+
+impl From<ash::InstanceError> for Error {
+    fn from(e: ash::InstanceError) -> Self {
+        Error::Instance(e)
+    }
+}
+
+impl From<winit::CreationError> for Error {
+    fn from(e: winit::CreationError) -> Self {
+        Error::Window(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
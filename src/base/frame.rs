@@ -0,0 +1,124 @@
+use ash::vk;
+use ash::version::*;
+use super::ri;
+use super::buffer::{BufferSlice, DeviceBuffer};
+use super::error::Result;
+use std::cell::RefCell;
+
+// App::begin_frame返回，RenderLoop在cmd_buffer上录制，App::end_frame提交并present
+pub struct FrameContext {
+    pub frame_index: usize,
+    pub cmd_buffer: vk::CommandBuffer,
+    // 获取到的swapchain图像，无窗口模式下为None
+    pub image_index: Option<u32>,
+    // image_index对应的surface framebuffer，无窗口模式下为null
+    pub frame_buffer: vk::Framebuffer,
+    pub extent: vk::Extent2D,
+}
+
+// 每帧独占的同步对象和临时资源，App按帧轮换使用，
+// CPU录制第N+1帧时GPU可以继续执行第N帧
+pub struct FrameResources {
+    pub cmd_buffer: vk::CommandBuffer,
+    // swapchain图像可用
+    pub image_available: vk::Semaphore,
+    // 本帧渲染完成，可以present
+    pub render_complete: vk::Semaphore,
+    // 本帧提交的命令执行完成，创建时为signaled状态
+    pub in_flight_fence: vk::Fence,
+    // 每帧清空的临时uniform buffer
+    pub transient_buffer: RefCell<DeviceBuffer>,
+    device: ash::Device,
+}
+
+impl FrameResources {
+    pub fn new(backend: &ri::Backend, cmd_pool: vk::CommandPool, transient_buffer_size: u64,
+               name: &str)
+        -> Result<Self>
+    {
+        let device = &backend.device;
+        let cmd_buffer = unsafe {
+            let ci = vk::CommandBufferAllocateInfo {
+                command_buffer_count: 1,
+                command_pool: cmd_pool,
+                level: vk::CommandBufferLevel::PRIMARY,
+                ..Default::default()
+            };
+            device.allocate_command_buffers(&ci)?[0]
+        };
+        let semaphore_ci = vk::SemaphoreCreateInfo::default();
+        let image_available = unsafe {
+            device.create_semaphore(&semaphore_ci, None)?
+        };
+        let render_complete = unsafe {
+            device.create_semaphore(&semaphore_ci, None)?
+        };
+        let in_flight_fence = unsafe {
+            let fence_ci = vk::FenceCreateInfo::builder()
+                .flags(vk::FenceCreateFlags::SIGNALED);
+            device.create_fence(&fence_ci, None)?
+        };
+        let transient_buffer = {
+            let device_memory_properties = unsafe {
+                backend.instance.get_physical_device_memory_properties(backend.physical_device)
+            };
+            let buffer_ci = vk::BufferCreateInfo::builder()
+                .size(transient_buffer_size)
+                .usage(vk::BufferUsageFlags::UNIFORM_BUFFER | vk::BufferUsageFlags::STORAGE_BUFFER)
+                .sharing_mode(vk::SharingMode::EXCLUSIVE)
+                .build();
+            let mut transient_buffer = DeviceBuffer::new(
+                device,
+                &device_memory_properties,
+                &buffer_ci,
+                vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+            )?;
+            let limits = &backend.physical_device_info.properties.limits;
+            transient_buffer.set_alignment(std::cmp::max(
+                limits.min_uniform_buffer_offset_alignment,
+                limits.min_storage_buffer_offset_alignment,
+            ));
+            transient_buffer
+        };
+
+        backend.set_object_name(cmd_buffer, &format!("{}_cmd_buffer", name));
+        backend.set_object_name(image_available, &format!("{}_image_available", name));
+        backend.set_object_name(render_complete, &format!("{}_render_complete", name));
+        backend.set_object_name(in_flight_fence, &format!("{}_in_flight_fence", name));
+        transient_buffer.set_name(backend, &format!("{}_transient_buffer", name));
+
+        Ok(FrameResources {
+            cmd_buffer,
+            image_available,
+            render_complete,
+            in_flight_fence,
+            transient_buffer: RefCell::new(transient_buffer),
+            device: device.clone(),
+        })
+    }
+
+    // 等待这一帧上一次提交的命令执行完，之后才能复用它的资源
+    pub fn wait(&self) -> Result<()>
+    {
+        unsafe {
+            self.device.wait_for_fences(&[self.in_flight_fence], true, u64::MAX)?;
+        }
+        self.transient_buffer.borrow_mut().clear();
+        Ok(())
+    }
+
+    pub fn allocate_transient<T>(&self, size: u64) -> BufferSlice<T>
+    {
+        self.transient_buffer.borrow_mut().allocate::<T>(size)
+    }
+}
+
+impl Drop for FrameResources {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_semaphore(self.image_available, None);
+            self.device.destroy_semaphore(self.render_complete, None);
+            self.device.destroy_fence(self.in_flight_fence, None);
+        }
+    }
+}
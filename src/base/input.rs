@@ -0,0 +1,201 @@
+use winit::{
+    DeviceEvent, ElementState, Event, MouseButton, MouseScrollDelta, VirtualKeyCode, WindowEvent,
+};
+use std::collections::HashSet;
+
+// 触摸板等按像素滚动时，换算成行数的比例
+const PIXELS_PER_LINE: f64 = 20.0;
+
+// 一帧内的键盘、鼠标状态，由App从winit事件更新，每帧结束时清空pressed/released和增量
+#[derive(Default)]
+pub struct InputState {
+    keys_down: HashSet<VirtualKeyCode>,
+    keys_pressed: HashSet<VirtualKeyCode>,
+    keys_released: HashSet<VirtualKeyCode>,
+    buttons_down: HashSet<MouseButton>,
+    buttons_pressed: HashSet<MouseButton>,
+    buttons_released: HashSet<MouseButton>,
+    // 窗口坐标（逻辑像素），光标不在窗口内时为None
+    cursor_position: Option<(f64, f64)>,
+    // 原始鼠标位移，不受光标加速和窗口边界影响，适合控制相机
+    cursor_delta: (f64, f64),
+    // 单位为行，向上为正
+    scroll_delta: (f64, f64),
+    focused: bool,
+}
+
+impl InputState {
+    pub fn new() -> Self
+    {
+        InputState {
+            focused: true,
+            ..Default::default()
+        }
+    }
+
+    pub fn handle_event(&mut self, event: &Event)
+    {
+        match event {
+            Event::WindowEvent { event, .. } => match event {
+                WindowEvent::KeyboardInput { input, .. } => {
+                    if let Some(key) = input.virtual_keycode {
+                        self.on_key(key, input.state);
+                    }
+                },
+                WindowEvent::MouseInput { state, button, .. } => self.on_mouse_button(*button, *state),
+                WindowEvent::CursorMoved { position, .. } => {
+                    self.cursor_position = Some((position.x, position.y));
+                },
+                WindowEvent::CursorLeft { .. } => self.cursor_position = None,
+                WindowEvent::MouseWheel { delta, .. } => match delta {
+                    MouseScrollDelta::LineDelta(x, y) => self.on_scroll(*x as f64, *y as f64),
+                    MouseScrollDelta::PixelDelta(pos) =>
+                        self.on_scroll(pos.x / PIXELS_PER_LINE, pos.y / PIXELS_PER_LINE),
+                },
+                WindowEvent::Focused(focused) => self.on_focus(*focused),
+                _ => {},
+            },
+            // 失去焦点时其他窗口中的鼠标移动也会上报，忽略
+            Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } if self.focused => {
+                self.cursor_delta.0 += delta.0;
+                self.cursor_delta.1 += delta.1;
+            },
+            _ => {},
+        }
+    }
+
+    // 每帧update和render之后调用
+    pub fn end_frame(&mut self)
+    {
+        self.keys_pressed.clear();
+        self.keys_released.clear();
+        self.buttons_pressed.clear();
+        self.buttons_released.clear();
+        self.cursor_delta = (0.0, 0.0);
+        self.scroll_delta = (0.0, 0.0);
+    }
+
+    // 按住
+    pub fn is_key_down(&self, key: VirtualKeyCode) -> bool
+    {
+        self.keys_down.contains(&key)
+    }
+
+    // 这一帧按下
+    pub fn is_key_pressed(&self, key: VirtualKeyCode) -> bool
+    {
+        self.keys_pressed.contains(&key)
+    }
+
+    // 这一帧松开
+    pub fn is_key_released(&self, key: VirtualKeyCode) -> bool
+    {
+        self.keys_released.contains(&key)
+    }
+
+    pub fn is_button_down(&self, button: MouseButton) -> bool
+    {
+        self.buttons_down.contains(&button)
+    }
+
+    pub fn is_button_pressed(&self, button: MouseButton) -> bool
+    {
+        self.buttons_pressed.contains(&button)
+    }
+
+    pub fn is_button_released(&self, button: MouseButton) -> bool
+    {
+        self.buttons_released.contains(&button)
+    }
+
+    pub fn cursor_position(&self) -> Option<(f64, f64)>
+    {
+        self.cursor_position
+    }
+
+    pub fn cursor_delta(&self) -> (f64, f64)
+    {
+        self.cursor_delta
+    }
+
+    pub fn scroll_delta(&self) -> (f64, f64)
+    {
+        self.scroll_delta
+    }
+
+    pub fn is_focused(&self) -> bool
+    {
+        self.focused
+    }
+
+    fn on_key(&mut self, key: VirtualKeyCode, state: ElementState)
+    {
+        match state {
+            ElementState::Pressed => {
+                // 系统的按键重复不算新的按下
+                if self.keys_down.insert(key) {
+                    self.keys_pressed.insert(key);
+                }
+            },
+            ElementState::Released => {
+                if self.keys_down.remove(&key) {
+                    self.keys_released.insert(key);
+                }
+            },
+        }
+    }
+
+    fn on_mouse_button(&mut self, button: MouseButton, state: ElementState)
+    {
+        match state {
+            ElementState::Pressed => {
+                if self.buttons_down.insert(button) {
+                    self.buttons_pressed.insert(button);
+                }
+            },
+            ElementState::Released => {
+                if self.buttons_down.remove(&button) {
+                    self.buttons_released.insert(button);
+                }
+            },
+        }
+    }
+
+    fn on_scroll(&mut self, x: f64, y: f64)
+    {
+        self.scroll_delta.0 += x;
+        self.scroll_delta.1 += y;
+    }
+
+    // 失去焦点时收不到松开事件，把按住的键都当作松开
+    fn on_focus(&mut self, focused: bool)
+    {
+        self.focused = focused;
+        if !focused {
+            self.keys_released.extend(self.keys_down.drain());
+            self.buttons_released.extend(self.buttons_down.drain());
+        }
+    }
+}
+
+
+#[test]
+fn test_input_state()
+{
+    let mut input = InputState::new();
+    input.on_key(VirtualKeyCode::W, ElementState::Pressed);
+    input.on_key(VirtualKeyCode::W, ElementState::Pressed);
+    assert!(input.is_key_pressed(VirtualKeyCode::W));
+    assert!(input.is_key_down(VirtualKeyCode::W));
+    input.on_scroll(0.0, 1.0);
+    input.end_frame();
+    assert!(!input.is_key_pressed(VirtualKeyCode::W));
+    assert!(input.is_key_down(VirtualKeyCode::W));
+    assert_eq!(input.scroll_delta(), (0.0, 0.0));
+
+    input.on_mouse_button(MouseButton::Left, ElementState::Pressed);
+    input.on_focus(false);
+    assert!(!input.is_key_down(VirtualKeyCode::W));
+    assert!(input.is_key_released(VirtualKeyCode::W));
+    assert!(input.is_button_released(MouseButton::Left));
+}
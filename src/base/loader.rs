@@ -1,68 +1,415 @@
 extern crate ash;
 pub use ash::version::{DeviceV1_0};
 use ash::{util::*, vk, Device};
-use std::path::Path;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
+use super::error::{Error, Result};
+use super::reflect::{self, ShaderReflection};
 
 const GLSLANG_VALIDATOR: &str = "glslangValidator";
 const INCLUDE_PATH: &str = "./shader/";
+// 编译结果按内容hash存放，不再写到源文件旁边
+const CACHE_DIR: &str = "./target/shader_cache/";
+// 参与cache key，修改编译参数后旧的缓存自动失效
+const COMPILE_ARGS: [&str; 1] = ["-V"];
 
-pub fn load_shader(device: &Device, path: &str)
--> Result<vk::ShaderModule, String>
+// 区分同一进程内并发编译的临时文件
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// 返回shader module和从SPIR-V解析出的反射信息
+pub fn load_shader(device: &Device, path: &str, defines: &[(String, String)])
+-> Result<(vk::ShaderModule, ShaderReflection)>
 {
-    // todo：检查文件是否更改，再生成spv
-    if !glsl_to_spv(path) {
-        return Err(format!("failed to create spv file, {:?}", path));
-    }
+    let spv_path = compile_cached(path, defines)?;
+    let bytes = std::fs::read(&spv_path)?;
+    let mut spv_file = std::io::Cursor::new(bytes);
+    let code = read_spv(&mut spv_file)?;
+    let reflection = reflect::reflect(&code)
+        .map_err(|message| Error::ShaderReflect {
+            path: path.to_string(),
+            message,
+        })?;
+    let ci = vk::ShaderModuleCreateInfo::builder()
+        .code(&code);
+    let module = unsafe {
+        device.create_shader_module(&ci, None)?
+    };
+    Ok((module, reflection))
+}
 
-    let spv_path = get_spv_path(path);
-    let bytes = match std::fs::read(&spv_path) {
-        Ok(t) => t,
-        Err(_e) => {
-            return Err(format!("failed to read spv file, {:?}", path));
+// 源文件、include、define和编译器都没变时直接返回缓存的spv路径
+// 缓存文件名为 {文件名}.{源码key}-{编译器key}.spv，找不到编译器时使用任意编译器生成的缓存
+fn compile_cached(path: &str, defines: &[(String, String)]) -> Result<PathBuf>
+{
+    let source = std::fs::read(path)?;
+    let includes = collect_includes(Path::new(path));
+    let key = {
+        let mut hasher = CacheKeyHasher::new();
+        hasher.field(&source);
+        for include in includes.iter() {
+            hasher.field(include.to_string_lossy().as_bytes());
+            hasher.field(&std::fs::read(include)?);
         }
-    };
-    let mut spv_file = std::io::Cursor::new(bytes);
-    let code = match read_spv(&mut spv_file) {
-        Ok(t) => t,
-        Err(_e) => {
-            return Err(format!("failed to read_spv, {}:{}", path, _e));
+        // 宏的顺序不影响编译结果
+        let mut defines = defines.to_vec();
+        defines.sort();
+        for (name, value) in defines.iter() {
+            hasher.field(name.as_bytes());
+            hasher.field(value.as_bytes());
         }
+        for arg in COMPILE_ARGS.iter() {
+            hasher.field(arg.as_bytes());
+        }
+        hasher.finish()
     };
-    let ci = vk::ShaderModuleCreateInfo::builder()
-        .code(&code);
-    unsafe {
-        Ok(device.create_shader_module(&ci, None)
-            .expect(&format!("shader module error, {:?}", spv_path)))
+
+    let file_name = Path::new(path).file_name().unwrap_or_default().to_string_lossy();
+    let prefix = format!("{}.{:016x}-", file_name, key);
+    let compiler = match compiler_identity() {
+        Some(compiler) => compiler,
+        None => {
+            if let Some(spv_path) = find_cached(&prefix) {
+                return Ok(spv_path);
+            }
+            // 没有缓存，让编译报告找不到编译器
+            String::new()
+        },
+    };
+    let compiler_key = {
+        let mut hasher = CacheKeyHasher::new();
+        hasher.field(compiler.as_bytes());
+        hasher.finish()
+    };
+    let spv_path = Path::new(CACHE_DIR).join(format!("{}{:016x}.spv", prefix, compiler_key));
+    if spv_path.exists() {
+        return Ok(spv_path);
+    }
+
+    // 先写到临时文件，编译中断时不会留下不完整的缓存
+    // 临时文件名带上pid和计数，并发编译同一个shader时互不覆盖
+    std::fs::create_dir_all(CACHE_DIR)?;
+    let tmp_path = spv_path.with_extension(format!(
+        "{}.{}.tmp",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    if let Err(e) = glsl_to_spv(path, &tmp_path, defines) {
+        let _ = std::fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+    if let Err(e) = std::fs::rename(&tmp_path, &spv_path) {
+        let _ = std::fs::remove_file(&tmp_path);
+        // 其他进程已经写好了同样的缓存
+        if !spv_path.exists() {
+            return Err(e.into());
+        }
+    }
+    Ok(spv_path)
+}
+
+// 缓存目录中以prefix开头的spv，有多个时取最新的
+fn find_cached(prefix: &str) -> Option<PathBuf>
+{
+    std::fs::read_dir(CACHE_DIR)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.starts_with(prefix) && name.ends_with(".spv")
+        })
+        .filter_map(|entry| {
+            let modified = entry.metadata().and_then(|metadata| metadata.modified()).ok()?;
+            Some((modified, entry.path()))
+        })
+        .max()
+        .map(|(_, path)| path)
+}
+
+// 64位FNV-1a，缓存文件名由它决定，不能用std的DefaultHasher（算法随Rust版本变化）
+struct CacheKeyHasher(u64);
+
+impl CacheKeyHasher {
+    fn new() -> Self
+    {
+        CacheKeyHasher(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8])
+    {
+        for &byte in bytes.iter() {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    // 先写长度，避免相邻字段拼接后产生相同的字节序列
+    fn field(&mut self, bytes: &[u8])
+    {
+        self.write(&(bytes.len() as u64).to_le_bytes());
+        self.write(bytes);
+    }
+
+    fn finish(&self) -> u64
+    {
+        self.0
+    }
+}
+
+// 用PATH中找到的编译器路径、大小和修改时间代表编译器版本，不用启动编译器
+// 找不到编译器时返回None
+fn compiler_identity() -> Option<String>
+{
+    let file_name = format!("{}{}", GLSLANG_VALIDATOR, std::env::consts::EXE_SUFFIX);
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|dir| dir.join(&file_name))
+        .find_map(|compiler| {
+            let metadata = std::fs::metadata(&compiler).ok()?;
+            if !metadata.is_file() {
+                return None;
+            }
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(SystemTime::UNIX_EPOCH).ok())
+                .map(|duration| duration.as_nanos())
+                .unwrap_or(0);
+            Some(format!("{}:{}:{}", compiler.display(), metadata.len(), modified))
+        })
+}
+
+// 递归收集path引用的所有include文件，按首次出现的顺序，找不到的文件留给编译器报错
+pub fn collect_includes(path: &Path) -> Vec<PathBuf>
+{
+    let mut includes = vec![];
+    let mut stack = vec![path.to_path_buf()];
+    while let Some(file) = stack.pop() {
+        let source = match std::fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(_) => continue,
+        };
+        let dir = file.parent().unwrap_or_else(|| Path::new("."));
+        let mut found = vec![];
+        for (name, relative) in parse_includes(&source) {
+            let local = dir.join(&name);
+            let global = Path::new(INCLUDE_PATH).join(&name);
+            let include = if relative && local.exists() { local } else { global };
+            if include.exists() && !includes.contains(&include) {
+                includes.push(include.clone());
+                found.push(include);
+            }
+        }
+        stack.extend(found.into_iter().rev());
+    }
+    includes
+}
+
+// #include "name" 返回(name, true)，#include <name> 返回(name, false)
+fn parse_includes(source: &str) -> Vec<(String, bool)>
+{
+    source
+        .lines()
+        .filter_map(|line| {
+            let rest = line.trim_start().strip_prefix("#")?.trim_start().strip_prefix("include")?.trim();
+            let (open, close, relative) = match rest.chars().next()? {
+                '"' => ('"', '"', true),
+                '<' => ('<', '>', false),
+                _ => return None,
+            };
+            let rest = rest.strip_prefix(open)?;
+            let end = rest.find(close)?;
+            Some((rest[..end].to_string(), relative))
+        })
+        .collect()
+}
+
+// 轮询着色器源文件及其include的修改时间，用于热重载
+pub struct ShaderWatcher {
+    // 源文件路径 -> 它和所有include的修改时间，文件不存在时为None
+    sources: BTreeMap<String, Vec<(PathBuf, Option<SystemTime>)>>,
+    pub poll_interval: Duration,
+    last_poll: Instant,
+}
+
+impl ShaderWatcher {
+    pub fn new(poll_interval: Duration) -> Self
+    {
+        ShaderWatcher {
+            sources: BTreeMap::new(),
+            poll_interval,
+            last_poll: Instant::now(),
+        }
+    }
+
+    // 重复watch同一个文件不会重置它的状态
+    pub fn watch(&mut self, path: &str)
+    {
+        if !self.sources.contains_key(path) {
+            self.sources.insert(path.to_string(), ShaderWatcher::snapshot(path));
+        }
+    }
+
+    // 返回上次poll之后有改动的源文件，距离上次poll不足poll_interval时返回空
+    pub fn poll(&mut self) -> Vec<String>
+    {
+        if self.last_poll.elapsed() < self.poll_interval {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+        let mut changed = vec![];
+        for (path, files) in self.sources.iter_mut() {
+            let modified = files
+                .iter()
+                .any(|(file, time)| ShaderWatcher::modified_time(file) != *time);
+            if modified {
+                // include可能也变了，重新收集
+                *files = ShaderWatcher::snapshot(path);
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+
+    fn snapshot(path: &str) -> Vec<(PathBuf, Option<SystemTime>)>
+    {
+        std::iter::once(PathBuf::from(path))
+            .chain(collect_includes(Path::new(path)))
+            .map(|file| {
+                let time = ShaderWatcher::modified_time(&file);
+                (file, time)
+            })
+            .collect()
+    }
+
+    fn modified_time(file: &Path) -> Option<SystemTime>
+    {
+        std::fs::metadata(file).and_then(|metadata| metadata.modified()).ok()
     }
 }
 
-fn get_spv_path(path: &str) -> String{
-    return String::from(path) + ".spv";
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+    Note,
 }
 
-fn glsl_to_spv(path: &str) -> bool{
-    let obj = Path::new(path);
-    if !obj.exists(){
-        println!("current dir: {:?}", std::env::current_dir());
-        return false;
+// 编译器输出中的一条诊断，line为None时没有具体位置
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShaderDiagnostic {
+    pub file: String,
+    pub line: Option<u32>,
+    pub severity: DiagnosticSeverity,
+    pub message: String,
+}
+
+impl fmt::Display for ShaderDiagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = match self.severity {
+            DiagnosticSeverity::Error => "error",
+            DiagnosticSeverity::Warning => "warning",
+            DiagnosticSeverity::Note => "note",
+        };
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}: {}", self.file, line, severity, self.message),
+            None => write!(f, "{}: {}: {}", self.file, severity, self.message),
+        }
+    }
+}
+
+// 解析glslangValidator的输出，格式为 "ERROR: file:line: message"
+// 文件名为"0"时指编译的源文件本身；"N compilation errors"之类没有位置的汇总行忽略
+pub fn parse_diagnostics(path: &str, output: &str) -> Vec<ShaderDiagnostic>
+{
+    const SEVERITIES: [(&str, DiagnosticSeverity); 5] = [
+        ("INTERNAL ERROR: ", DiagnosticSeverity::Error),
+        ("UNIMPLEMENTED: ", DiagnosticSeverity::Error),
+        ("ERROR: ", DiagnosticSeverity::Error),
+        ("WARNING: ", DiagnosticSeverity::Warning),
+        ("NOTE: ", DiagnosticSeverity::Note),
+    ];
+    output
+        .lines()
+        .filter_map(|line| {
+            let (rest, severity) = SEVERITIES
+                .iter()
+                .find_map(|&(prefix, severity)| line.strip_prefix(prefix).map(|rest| (rest, severity)))?;
+            // 文件名可能包含':'（Windows盘符），取第一个后面紧跟 数字: 的':'
+            let (file, line_number, message) = rest
+                .match_indices(':')
+                .find_map(|(i, _)| {
+                    let after = &rest[i + 1..];
+                    let digits = after.find(|c: char| !c.is_ascii_digit())?;
+                    if digits == 0 || !after[digits..].starts_with(':') {
+                        return None;
+                    }
+                    let line_number = after[..digits].parse::<u32>().ok()?;
+                    Some((&rest[..i], line_number, after[digits + 1..].trim()))
+                })?;
+            Some(ShaderDiagnostic {
+                file: if file == "0" { path.to_string() } else { file.to_string() },
+                line: Some(line_number),
+                severity,
+                message: message.to_string(),
+            })
+        })
+        .collect()
+}
+
+fn compiler_start_error(path: &str, e: std::io::Error) -> Error
+{
+    Error::ShaderCompile {
+        path: path.to_string(),
+        diagnostics: vec![ShaderDiagnostic {
+            file: GLSLANG_VALIDATOR.to_string(),
+            line: None,
+            severity: DiagnosticSeverity::Error,
+            message: format!("failed to start: {}", e),
+        }],
     }
+}
 
-    let spv_path = get_spv_path(path);
-    let cmd = Command::new(GLSLANG_VALIDATOR)
-        .arg("-V")
+// 编译失败时返回编译器给出的诊断，成功时警告写入日志
+fn glsl_to_spv(path: &str, spv_path: &Path, defines: &[(String, String)]) -> Result<()>
+{
+    let output = Command::new(GLSLANG_VALIDATOR)
+        .args(COMPILE_ARGS)
         .arg(format!("-I{}", INCLUDE_PATH))
+        .args(defines.iter().map(|(name, value)| format!("-D{}={}", name, value)))
         .arg("-o")
-        .arg(&spv_path)
+        .arg(spv_path)
         // .arg("-g")
         // .arg("-Od")
         .arg(path)
         .output()
-        .expect(&format!("{:?} command failed to start", GLSLANG_VALIDATOR));
+        .map_err(|e| compiler_start_error(path, e))?;
 
-    println!("glsl_to_spv output: {:?}", String::from_utf8_lossy(&cmd.stdout));
-
-    return true;
+    // 诊断输出在stdout，部分版本的部分错误在stderr
+    let text = format!("{}{}", String::from_utf8_lossy(&output.stdout), String::from_utf8_lossy(&output.stderr));
+    let mut diagnostics = parse_diagnostics(path, &text);
+    if !output.status.success() || !spv_path.exists() {
+        if !diagnostics.iter().any(|diagnostic| diagnostic.severity == DiagnosticSeverity::Error) {
+            diagnostics.push(ShaderDiagnostic {
+                file: path.to_string(),
+                line: None,
+                severity: DiagnosticSeverity::Error,
+                message: format!("compiler exited with {}: {}", output.status, text.trim()),
+            });
+        }
+        return Err(Error::ShaderCompile {
+            path: path.to_string(),
+            diagnostics,
+        });
+    }
+    for diagnostic in diagnostics.iter() {
+        log::warn!("{}", diagnostic);
+    }
+    Ok(())
 }
 
 
@@ -70,15 +417,86 @@ fn glsl_to_spv(path: &str) -> bool{
 fn test_glsl_to_spv()
 {
     let glsl_path = format!("./shader/test/triangle.vert");
-    let spv_path = format!("{}.spv", glsl_path);
-    let spv_obj= std::path::Path::new(&spv_path);
-    if spv_obj.exists() {
+    let spv_path = std::env::temp_dir().join("rt_vk_example_triangle.vert.spv");
+    if spv_path.exists() {
         std::fs::remove_file(&spv_path)
             .expect("删除失败？");
     }
     println!("current path: {:?}", std::env::current_dir());
     println!("glsl path: {:?}", glsl_path);
     println!("spv path: {:?}", spv_path);
-    glsl_to_spv(&glsl_path);
-    assert!(spv_obj.exists(), "生成spv文件失败");
+    glsl_to_spv(&glsl_path, &spv_path, &[])
+        .expect("编译失败");
+    assert!(spv_path.exists(), "生成spv文件失败");
+}
+
+#[test]
+fn test_parse_includes()
+{
+    let source = "#version 450\n#include \"common.glsl\"\n  # include <lighting/brdf.glsl>\n// #include \"x\"\n";
+    let includes = parse_includes(source);
+    assert_eq!(includes, vec![
+        ("common.glsl".to_string(), true),
+        ("lighting/brdf.glsl".to_string(), false),
+    ]);
+}
+
+#[test]
+fn test_shader_watcher()
+{
+    let dir = std::env::temp_dir().join("rt_vk_example_watcher");
+    std::fs::create_dir_all(&dir).unwrap();
+    let common = dir.join("common.glsl");
+    let source = dir.join("watched.frag");
+    std::fs::write(&common, "vec4 tint() { return vec4(1.0); }\n").unwrap();
+    std::fs::write(&source, "#version 450\n#include \"common.glsl\"\n").unwrap();
+
+    let source = source.to_string_lossy().into_owned();
+    let mut watcher = ShaderWatcher::new(Duration::from_secs(0));
+    watcher.watch(&source);
+    assert!(watcher.poll().is_empty());
+    // 删除include也算改动
+    std::fs::remove_file(&common).unwrap();
+    assert_eq!(watcher.poll(), vec![source.clone()]);
+    assert!(watcher.poll().is_empty());
+}
+
+#[test]
+fn test_parse_diagnostics()
+{
+    let output = "./shader/cube/cube.frag\n\
+        ERROR: ./shader/cube/cube.frag:12: 'colr' : undeclared identifier\n\
+        WARNING: 0:3: '#extension' : extension not supported: GL_foo\n\
+        ERROR: C:/shader/common.glsl:7: '' : syntax error\n\
+        ERROR: 2 compilation errors.  No code generated.\n";
+    let diagnostics = parse_diagnostics("./shader/cube/cube.frag", output);
+    assert_eq!(diagnostics.len(), 3);
+    assert_eq!(diagnostics[0], ShaderDiagnostic {
+        file: "./shader/cube/cube.frag".to_string(),
+        line: Some(12),
+        severity: DiagnosticSeverity::Error,
+        message: "'colr' : undeclared identifier".to_string(),
+    });
+    assert_eq!(diagnostics[1].file, "./shader/cube/cube.frag");
+    assert_eq!(diagnostics[1].severity, DiagnosticSeverity::Warning);
+    assert_eq!(diagnostics[2].file, "C:/shader/common.glsl");
+    assert_eq!(diagnostics[2].line, Some(7));
+}
+
+#[test]
+fn test_cache_key_hasher()
+{
+    // FNV-1a的标准测试值，保证缓存文件名在不同平台、不同Rust版本下一致
+    let mut hasher = CacheKeyHasher::new();
+    hasher.write(b"a");
+    assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+
+    let key = |fields: &[&[u8]]| {
+        let mut hasher = CacheKeyHasher::new();
+        for field in fields.iter() {
+            hasher.field(field);
+        }
+        hasher.finish()
+    };
+    assert_ne!(key(&[b"ab", b"c"]), key(&[b"a", b"bc"]));
 }
@@ -0,0 +1,306 @@
+use ash::vk;
+use ash::version::*;
+use super::ri;
+use super::error::Result;
+use std::cell::{Cell, RefCell};
+
+// 每帧最多记录的scope数量，超出的scope不计时
+const MAX_SCOPES_PER_FRAME: u32 = 64;
+
+// 一个pass的GPU耗时，children为嵌套在其中的scope
+#[derive(Clone, Debug, PartialEq)]
+pub struct PassTiming {
+    pub name: String,
+    pub time_ms: f64,
+    pub children: Vec<PassTiming>,
+}
+
+struct ScopeRecord {
+    name: String,
+    depth: usize,
+    begin_query: u32,
+    end_query: Option<u32>,
+}
+
+// 一个frame in flight对应的query区间
+#[derive(Default)]
+struct FrameQueries {
+    records: Vec<ScopeRecord>,
+    query_count: u32,
+    depth: usize,
+}
+
+// 基于timestamp query的GPU profiler
+// 每个frame in flight使用query pool中独立的一段，下次轮到这一帧时（fence已经等待过）
+// 再读取结果，不会阻塞GPU
+pub struct GpuProfiler {
+    query_pool: vk::QueryPool,
+    frames: Vec<RefCell<FrameQueries>>,
+    current_frame: Cell<Option<usize>>,
+    // 每个tick的纳秒数
+    timestamp_period: f64,
+    timestamp_mask: u64,
+    results: RefCell<Vec<PassTiming>>,
+    device: ash::Device,
+}
+
+// 离开作用域时写入结束时间戳
+pub struct GpuScope<'a> {
+    profiler: &'a GpuProfiler,
+    cmd_buf: vk::CommandBuffer,
+    record_index: Option<usize>,
+}
+
+impl<'a> Drop for GpuScope<'a> {
+    fn drop(&mut self) {
+        if let Some(record_index) = self.record_index {
+            self.profiler.end_scope(self.cmd_buf, record_index);
+        }
+    }
+}
+
+impl GpuProfiler {
+    pub fn new(backend: &ri::Backend, frames_in_flight: usize) -> Result<Self>
+    {
+        let device = &backend.device;
+        let info = &backend.physical_device_info;
+        let valid_bits = info.queue_families[backend.queue_family_index as usize].timestamp_valid_bits;
+        // graphic队列不支持timestamp时profiler不做任何事
+        let query_pool = match valid_bits {
+            0 => vk::QueryPool::null(),
+            _ => unsafe {
+                let query_pool_ci = vk::QueryPoolCreateInfo {
+                    query_type: vk::QueryType::TIMESTAMP,
+                    query_count: MAX_SCOPES_PER_FRAME * 2 * frames_in_flight as u32,
+                    ..Default::default()
+                };
+                device.create_query_pool(&query_pool_ci, None)?
+            },
+        };
+        if query_pool != vk::QueryPool::null() {
+            backend.set_object_name(query_pool, "gpu_profiler_query_pool");
+        }
+        let timestamp_mask = match valid_bits {
+            64 => u64::MAX,
+            bits => (1u64 << bits) - 1,
+        };
+        Ok(GpuProfiler {
+            query_pool,
+            frames: (0..frames_in_flight).map(|_| RefCell::new(FrameQueries::default())).collect(),
+            current_frame: Cell::new(None),
+            timestamp_period: info.properties.limits.timestamp_period as f64,
+            timestamp_mask,
+            results: RefCell::new(vec![]),
+            device: device.clone(),
+        })
+    }
+
+    pub fn is_supported(&self) -> bool
+    {
+        self.query_pool != vk::QueryPool::null()
+    }
+
+    // 在frame_index的fence等待完成、command buffer开始录制后调用
+    // 读取这一帧上一次的结果，重置query，并开始根scope "frame"
+    pub fn begin_frame(&self, frame_index: usize, cmd_buf: vk::CommandBuffer) -> Result<()>
+    {
+        if !self.is_supported() {
+            return Ok(());
+        }
+        self.resolve(frame_index)?;
+        *self.frames[frame_index].borrow_mut() = FrameQueries::default();
+        unsafe {
+            self.device.cmd_reset_query_pool(
+                cmd_buf,
+                self.query_pool,
+                self.first_query(frame_index),
+                MAX_SCOPES_PER_FRAME * 2,
+            );
+        }
+        self.current_frame.set(Some(frame_index));
+        self.begin_scope(cmd_buf, "frame");
+        Ok(())
+    }
+
+    // 在command buffer结束录制前调用，结束根scope
+    pub fn end_frame(&self, cmd_buf: vk::CommandBuffer)
+    {
+        if self.current_frame.get().is_some() {
+            self.end_scope(cmd_buf, 0);
+        }
+        self.current_frame.set(None);
+    }
+
+    // 开始一个命名scope，返回的GpuScope离开作用域时结束
+    pub fn scope(&self, cmd_buf: vk::CommandBuffer, name: &str) -> GpuScope<'_>
+    {
+        GpuScope {
+            profiler: self,
+            cmd_buf,
+            record_index: self.begin_scope(cmd_buf, name),
+        }
+    }
+
+    // 最近一次读取到的结果，比当前帧晚frames in flight帧
+    pub fn results(&self) -> Vec<PassTiming>
+    {
+        self.results.borrow().clone()
+    }
+
+    // 按层级缩进输出
+    pub fn report(&self) -> String
+    {
+        let mut report = String::new();
+        for pass in self.results.borrow().iter() {
+            format_pass(pass, 0, &mut report);
+        }
+        report
+    }
+
+    fn first_query(&self, frame_index: usize) -> u32
+    {
+        frame_index as u32 * MAX_SCOPES_PER_FRAME * 2
+    }
+
+    fn begin_scope(&self, cmd_buf: vk::CommandBuffer, name: &str) -> Option<usize>
+    {
+        let frame_index = self.current_frame.get()?;
+        let mut frame = self.frames[frame_index].borrow_mut();
+        // 给已经开始的scope（包括根scope）预留结束query
+        if frame.query_count + 2 + frame.depth as u32 > MAX_SCOPES_PER_FRAME * 2 {
+            return None;
+        }
+        let begin_query = frame.query_count;
+        frame.query_count += 1;
+        unsafe {
+            self.device.cmd_write_timestamp(
+                cmd_buf,
+                vk::PipelineStageFlags::TOP_OF_PIPE,
+                self.query_pool,
+                self.first_query(frame_index) + begin_query,
+            );
+        }
+        let depth = frame.depth;
+        frame.depth += 1;
+        frame.records.push(ScopeRecord {
+            name: name.to_string(),
+            depth,
+            begin_query,
+            end_query: None,
+        });
+        Some(frame.records.len() - 1)
+    }
+
+    fn end_scope(&self, cmd_buf: vk::CommandBuffer, record_index: usize)
+    {
+        let frame_index = match self.current_frame.get() {
+            Some(frame_index) => frame_index,
+            None => return,
+        };
+        let mut frame = self.frames[frame_index].borrow_mut();
+        let end_query = frame.query_count;
+        frame.query_count += 1;
+        unsafe {
+            self.device.cmd_write_timestamp(
+                cmd_buf,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                self.query_pool,
+                self.first_query(frame_index) + end_query,
+            );
+        }
+        frame.depth -= 1;
+        frame.records[record_index].end_query = Some(end_query);
+    }
+
+    fn resolve(&self, frame_index: usize) -> Result<()>
+    {
+        let frame = self.frames[frame_index].borrow();
+        if frame.query_count == 0 {
+            return Ok(());
+        }
+        let mut timestamps = vec![0u64; frame.query_count as usize];
+        let query_result = unsafe {
+            self.device.get_query_pool_results(
+                self.query_pool,
+                self.first_query(frame_index),
+                frame.query_count,
+                &mut timestamps,
+                vk::QueryResultFlags::TYPE_64,
+            )
+        };
+        match query_result {
+            Ok(()) => {},
+            // 这一帧的command buffer没有提交（例如swapchain过期后跳过了这一帧），
+            // query没有写入，这一帧没有数据，保留上一次的结果
+            Err(vk::Result::NOT_READY) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+        let flat = frame.records
+            .iter()
+            .filter_map(|record| {
+                let end_query = record.end_query?;
+                let ticks = timestamps[end_query as usize]
+                    .wrapping_sub(timestamps[record.begin_query as usize])
+                    & self.timestamp_mask;
+                let time_ms = ticks as f64 * self.timestamp_period / 1_000_000.0;
+                Some((record.name.clone(), record.depth, time_ms))
+            })
+            .collect::<Vec<(String, usize, f64)>>();
+        *self.results.borrow_mut() = build_tree(&flat);
+        Ok(())
+    }
+}
+
+impl Drop for GpuProfiler {
+    fn drop(&mut self) {
+        if self.is_supported() {
+            unsafe {
+                self.device.destroy_query_pool(self.query_pool, None);
+            }
+        }
+    }
+}
+
+// 按录制顺序排列的(name, depth, time)还原成树
+pub fn build_tree(flat: &[(String, usize, f64)]) -> Vec<PassTiming>
+{
+    fn build(flat: &[(String, usize, f64)], pos: &mut usize, depth: usize) -> Vec<PassTiming> {
+        let mut passes = vec![];
+        while *pos < flat.len() && flat[*pos].1 >= depth {
+            let (name, _, time_ms) = flat[*pos].clone();
+            *pos += 1;
+            let children = build(flat, pos, depth + 1);
+            passes.push(PassTiming { name, time_ms, children });
+        }
+        passes
+    }
+    let mut pos = 0;
+    build(flat, &mut pos, 0)
+}
+
+fn format_pass(pass: &PassTiming, indent: usize, report: &mut String)
+{
+    report.push_str(&format!("{}{}: {:.3} ms\n", "  ".repeat(indent), pass.name, pass.time_ms));
+    for child in pass.children.iter() {
+        format_pass(child, indent + 1, report);
+    }
+}
+
+
+#[test]
+fn test_build_tree()
+{
+    let flat = vec![
+        ("frame".to_string(), 0, 3.0),
+        ("shadow".to_string(), 1, 1.0),
+        ("opaque".to_string(), 1, 1.5),
+        ("sky".to_string(), 2, 0.5),
+        ("post".to_string(), 1, 0.5),
+    ];
+    let tree = build_tree(&flat);
+    assert_eq!(tree.len(), 1);
+    assert_eq!(tree[0].name, "frame");
+    let children = tree[0].children.iter().map(|pass| pass.name.as_str()).collect::<Vec<&str>>();
+    assert_eq!(children, vec!["shadow", "opaque", "post"]);
+    assert_eq!(tree[0].children[1].children[0].name, "sky");
+}
@@ -1,8 +1,17 @@
 use ash;
 use ash::vk;
 use ash::version::*;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use super::ri;
+use super::utility;
+use super::error::{Error, Result};
+use super::reflect::ShaderReflection;
 #[derive(Clone, Debug)]
 pub struct PipelineStateObjectDescriptor {
+    // 调试名，pipeline、render pass等对象以此为前缀命名
+    pub name: String,
     pub vs_desc: ShaderProgramDescriptor,
     pub ps_desc: ShaderProgramDescriptor,
     pub attachment_desc: Vec<vk::AttachmentDescription>,
@@ -10,17 +19,26 @@ pub struct PipelineStateObjectDescriptor {
     pub input_attr_desc: Vec<vk::VertexInputAttributeDescription>,
     pub viewports: Vec<vk::Viewport>,
     pub scissors: Vec<vk::Rect2D>,
+    // 按set序号排列，每个set的binding，创建PSO时生成对应的vk::DescriptorSetLayout
+    pub descriptor_set_layouts: Vec<Vec<vk::DescriptorSetLayoutBinding>>,
+    // 每个stage最多出现在一个range中，为空时按着色器反射生成
+    pub push_constant_ranges: Vec<vk::PushConstantRange>,
+    // reversed-Z时为GREATER_OR_EQUAL，depth清除为0，见Camera::depth_compare_op
+    pub depth_compare_op: vk::CompareOp,
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct ShaderProgramDescriptor {
     pub path: String,
     pub entry: std::ffi::CString,
+    // 预处理宏(名字, 值)，以-D传给编译器，参与编译缓存的key
+    pub defines: Vec<(String, String)>,
 }
 
 impl ::std::default::Default for PipelineStateObjectDescriptor {
     fn default() -> Self {
         PipelineStateObjectDescriptor {
+            name: String::new(),
             vs_desc: ShaderProgramDescriptor::default(),
             ps_desc: ShaderProgramDescriptor::default(),
             attachment_desc: vec![],
@@ -28,28 +46,221 @@ impl ::std::default::Default for PipelineStateObjectDescriptor {
             input_binding_desc: vec![],
             viewports: vec![],
             scissors: vec![],
+            descriptor_set_layouts: vec![],
+            push_constant_ranges: vec![],
+            depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
         }
     }
 }
 
+impl PipelineStateObjectDescriptor {
+    // viewport和scissor覆盖整个extent，窗口尺寸变化后调用
+    pub fn set_viewport_extent(&mut self, extent: vk::Extent2D) {
+        self.viewports = vec![vk::Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: extent.width as f32,
+            height: extent.height as f32,
+            min_depth: 0.0,
+            max_depth: 1.0,
+        }];
+        self.scissors = vec![vk::Rect2D {
+            offset: vk::Offset2D { x: 0, y: 0 },
+            extent,
+        }];
+    }
+}
+
 pub struct PipelineStateObject {
     pub pso_desc: PipelineStateObjectDescriptor,
     pub vs_mod: vk::ShaderModule,
     pub ps_mod: vk::ShaderModule,
+    pub vs_reflection: ShaderReflection,
+    pub ps_reflection: ShaderReflection,
     pub render_pass: vk::RenderPass,
     pub pipeline_layout: vk::PipelineLayout,
+    // 与pso_desc.descriptor_set_layouts一一对应，用于分配descriptor set
+    pub descriptor_set_layouts: Vec<vk::DescriptorSetLayout>,
     pub pipeline: vk::Pipeline,
     pub device: ash::Device,
 }
 
+impl PipelineStateObject {
+    // 写入[offset, offset + size_of::<T>())的push constant，stage由完整包含这段范围的range决定
+    pub fn cmd_push_constants<T: Copy>(&self, cmd_buf: vk::CommandBuffer, offset: u32, data: &T)
+        -> Result<()>
+    {
+        let size = std::mem::size_of::<T>() as u32;
+        let stage_flags = push_constant_stages(&self.pso_desc.push_constant_ranges, offset, size)
+            .map_err(|message| Error::PushConstantRange {
+                name: self.pso_desc.name.clone(),
+                message,
+            })?;
+        unsafe {
+            let bytes = std::slice::from_raw_parts(data as *const T as *const u8, size as usize);
+            self.device.cmd_push_constants(cmd_buf, self.pipeline_layout, stage_flags, offset, bytes);
+        }
+        Ok(())
+    }
+}
+
+// 完整包含[offset, offset + size)的range的stage
+// vkCmdPushConstants要求与更新范围重叠的range都包含它，只覆盖一部分时返回错误
+pub fn push_constant_stages(ranges: &[vk::PushConstantRange], offset: u32, size: u32)
+    -> std::result::Result<vk::ShaderStageFlags, String>
+{
+    let end = offset + size;
+    let mut stages = vk::ShaderStageFlags::empty();
+    for range in ranges.iter().filter(|range| range.offset < end && offset < range.offset + range.size) {
+        if range.offset > offset || end > range.offset + range.size {
+            return Err(format!(
+                "update [{}, {}) is only partially covered by the {:?} range [{}, {})",
+                offset, end, range.stage_flags, range.offset, range.offset + range.size,
+            ));
+        }
+        stages |= range.stage_flags;
+    }
+    if stages.is_empty() {
+        return Err(format!("update [{}, {}) is not covered by any declared range", offset, end));
+    }
+    Ok(stages)
+}
+
+// 同一组着色器按宏组合生成的PSO，第一次用到某个组合时编译并创建
+// let variants = PipelineVariants::new(&backend, pso_desc);
+// let pso_obj = variants.get(&["HAS_NORMAL_MAP", "ALPHA_TEST"])?;
+pub struct PipelineVariants {
+    pub base_desc: PipelineStateObjectDescriptor,
+    // key为排序去重后的宏
+    variants: RefCell<BTreeMap<Vec<String>, Rc<PipelineStateObject>>>,
+    backend: Rc<ri::Backend>,
+}
+
+impl PipelineVariants {
+    pub fn new(backend: &Rc<ri::Backend>, base_desc: PipelineStateObjectDescriptor) -> Self
+    {
+        PipelineVariants {
+            base_desc,
+            variants: RefCell::new(BTreeMap::new()),
+            backend: backend.clone(),
+        }
+    }
+
+    // features中的宏定义为1，同时传给vs和ps；宏的顺序不影响结果
+    // 返回的Rc只在录制这一帧时持有，不要跨帧保存
+    pub fn get(&self, features: &[&str]) -> Result<Rc<PipelineStateObject>>
+    {
+        let key = variant_key(features);
+        if let Some(pso_obj) = self.variants.borrow().get(&key) {
+            return Ok(pso_obj.clone());
+        }
+        let desc = self.variant_desc(&key);
+        let pso_obj: Rc<PipelineStateObject> = utility::create_pipeline_state_object(&self.backend, &desc)?.into();
+        self.variants.borrow_mut().insert(key, pso_obj.clone());
+        Ok(pso_obj)
+    }
+
+    // 已创建的variant，用于热重载和窗口尺寸变化后更新viewport，此时不能有get返回的Rc还在使用
+    pub fn variants_mut(&mut self) -> Vec<&mut PipelineStateObject>
+    {
+        self.variants
+            .get_mut()
+            .iter_mut()
+            .map(|(key, pso_obj)| {
+                Rc::get_mut(pso_obj)
+                    .unwrap_or_else(|| panic!("pipeline variant {:?} is still in use", key))
+            })
+            .collect()
+    }
+
+    // 之后创建的variant也使用新的viewport
+    pub fn set_viewport_extent(&mut self, extent: vk::Extent2D)
+    {
+        self.base_desc.set_viewport_extent(extent);
+        for pso_obj in self.variants_mut() {
+            pso_obj.pso_desc.set_viewport_extent(extent);
+        }
+    }
+
+    fn variant_desc(&self, key: &[String]) -> PipelineStateObjectDescriptor
+    {
+        let mut desc = self.base_desc.clone();
+        if !key.is_empty() {
+            desc.name = format!("{}[{}]", desc.name, key.join("+"));
+        }
+        for shader_desc in [&mut desc.vs_desc, &mut desc.ps_desc].iter_mut() {
+            shader_desc.defines.extend(key.iter().map(|name| (name.clone(), "1".to_string())));
+        }
+        desc
+    }
+}
+
+pub fn variant_key(features: &[&str]) -> Vec<String>
+{
+    let mut key = features.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+    key.sort();
+    key.dedup();
+    key
+}
+
 impl Drop for PipelineStateObject {
     fn drop(&mut self) {
         unsafe {
             self.device.destroy_pipeline(self.pipeline, None);
             self.device.destroy_pipeline_layout(self.pipeline_layout, None);
+            for &set_layout in self.descriptor_set_layouts.iter() {
+                self.device.destroy_descriptor_set_layout(set_layout, None);
+            }
             self.device.destroy_shader_module(self.vs_mod, None);
             self.device.destroy_shader_module(self.ps_mod, None);
             self.device.destroy_render_pass(self.render_pass, None);
         }
     }
 }
+
+
+#[test]
+fn test_push_constant_stages()
+{
+    let ranges = [
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: 0,
+            size: 64,
+        },
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 64,
+            size: 16,
+        },
+    ];
+    assert_eq!(push_constant_stages(&ranges, 0, 64), Ok(vk::ShaderStageFlags::VERTEX));
+    assert_eq!(push_constant_stages(&ranges, 64, 4), Ok(vk::ShaderStageFlags::FRAGMENT));
+    // 跨两个range，两者都不完整包含
+    assert!(push_constant_stages(&ranges, 60, 8).is_err());
+    assert!(push_constant_stages(&ranges, 80, 4).is_err());
+
+    let shared = [
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::VERTEX,
+            offset: 0,
+            size: 80,
+        },
+        vk::PushConstantRange {
+            stage_flags: vk::ShaderStageFlags::FRAGMENT,
+            offset: 64,
+            size: 16,
+        },
+    ];
+    assert_eq!(push_constant_stages(&shared, 64, 16),
+               Ok(vk::ShaderStageFlags::VERTEX | vk::ShaderStageFlags::FRAGMENT));
+    assert_eq!(push_constant_stages(&shared, 0, 16), Ok(vk::ShaderStageFlags::VERTEX));
+}
+
+#[test]
+fn test_variant_key()
+{
+    assert_eq!(variant_key(&["ALPHA_TEST", "HAS_NORMAL_MAP", "ALPHA_TEST"]),
+               variant_key(&["HAS_NORMAL_MAP", "ALPHA_TEST"]));
+    assert!(variant_key(&[]).is_empty());
+}
@@ -0,0 +1,190 @@
+use ash::vk;
+use ash::version::*;
+
+// 队列族所有权转移（EXCLUSIVE资源在不同队列族之间使用时需要）
+// 源队列上录制release，目标队列上录制acquire，两次提交之间用semaphore同步
+// 源和目标是同一个队列族时（没有专用队列的回退情况）不需要转移，release不做任何事，
+// acquire录制一个普通barrier，完成layout转换和src到dst的执行依赖
+#[derive(Clone, Copy, Debug)]
+pub struct QueueOwnershipTransfer {
+    pub src_queue_family_index: u32,
+    pub dst_queue_family_index: u32,
+    pub src_stage_mask: vk::PipelineStageFlags,
+    pub src_access_mask: vk::AccessFlags,
+    pub dst_stage_mask: vk::PipelineStageFlags,
+    pub dst_access_mask: vk::AccessFlags,
+}
+
+impl QueueOwnershipTransfer {
+    pub fn is_needed(&self) -> bool
+    {
+        self.src_queue_family_index != self.dst_queue_family_index
+    }
+
+    // 不需要转移时barrier中的队列族都为IGNORED
+    fn queue_family_indices(&self) -> (u32, u32)
+    {
+        if self.is_needed() {
+            (self.src_queue_family_index, self.dst_queue_family_index)
+        } else {
+            (vk::QUEUE_FAMILY_IGNORED, vk::QUEUE_FAMILY_IGNORED)
+        }
+    }
+
+    // acquire的(src_stage, src_access)，不需要转移时acquire同时承担release的同步
+    fn acquire_src_scope(&self) -> (vk::PipelineStageFlags, vk::AccessFlags)
+    {
+        if self.is_needed() {
+            (vk::PipelineStageFlags::TOP_OF_PIPE, vk::AccessFlags::empty())
+        } else {
+            (self.src_stage_mask, self.src_access_mask)
+        }
+    }
+
+    fn buffer_barrier(&self, buffer: vk::Buffer, offset: u64, size: u64,
+                      src_access_mask: vk::AccessFlags, dst_access_mask: vk::AccessFlags)
+        -> vk::BufferMemoryBarrier
+    {
+        let (src_queue_family_index, dst_queue_family_index) = self.queue_family_indices();
+        vk::BufferMemoryBarrier {
+            src_access_mask,
+            dst_access_mask,
+            src_queue_family_index,
+            dst_queue_family_index,
+            buffer,
+            offset,
+            size,
+            ..Default::default()
+        }
+    }
+
+    fn image_barrier(&self, image: vk::Image, subresource_range: vk::ImageSubresourceRange,
+                     old_layout: vk::ImageLayout, new_layout: vk::ImageLayout,
+                     src_access_mask: vk::AccessFlags, dst_access_mask: vk::AccessFlags)
+        -> vk::ImageMemoryBarrier
+    {
+        let (src_queue_family_index, dst_queue_family_index) = self.queue_family_indices();
+        vk::ImageMemoryBarrier {
+            src_access_mask,
+            dst_access_mask,
+            old_layout,
+            new_layout,
+            src_queue_family_index,
+            dst_queue_family_index,
+            image,
+            subresource_range,
+            ..Default::default()
+        }
+    }
+
+    // 在源队列的command buffer上录制
+    pub fn cmd_release_buffer(&self, device: &ash::Device, cmd_buf: vk::CommandBuffer,
+                              buffer: vk::Buffer, offset: u64, size: u64)
+    {
+        if !self.is_needed() {
+            return;
+        }
+        let barrier = self.buffer_barrier(
+            buffer, offset, size, self.src_access_mask, vk::AccessFlags::empty());
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd_buf,
+                self.src_stage_mask,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+        }
+    }
+
+    // 在目标队列的command buffer上录制，需要在release所在的提交之后提交
+    pub fn cmd_acquire_buffer(&self, device: &ash::Device, cmd_buf: vk::CommandBuffer,
+                              buffer: vk::Buffer, offset: u64, size: u64)
+    {
+        let (src_stage_mask, src_access_mask) = self.acquire_src_scope();
+        let barrier = self.buffer_barrier(
+            buffer, offset, size, src_access_mask, self.dst_access_mask);
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd_buf,
+                src_stage_mask,
+                self.dst_stage_mask,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[barrier],
+                &[],
+            );
+        }
+    }
+
+    // layout转换和所有权转移一起完成，release和acquire需要传入相同的layout
+    pub fn cmd_release_image(&self, device: &ash::Device, cmd_buf: vk::CommandBuffer,
+                             image: vk::Image, subresource_range: vk::ImageSubresourceRange,
+                             old_layout: vk::ImageLayout, new_layout: vk::ImageLayout)
+    {
+        if !self.is_needed() {
+            return;
+        }
+        let barrier = self.image_barrier(
+            image, subresource_range, old_layout, new_layout,
+            self.src_access_mask, vk::AccessFlags::empty());
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd_buf,
+                self.src_stage_mask,
+                vk::PipelineStageFlags::BOTTOM_OF_PIPE,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+        }
+    }
+
+    // 不需要转移时layout转换在这里完成
+    pub fn cmd_acquire_image(&self, device: &ash::Device, cmd_buf: vk::CommandBuffer,
+                             image: vk::Image, subresource_range: vk::ImageSubresourceRange,
+                             old_layout: vk::ImageLayout, new_layout: vk::ImageLayout)
+    {
+        let (src_stage_mask, src_access_mask) = self.acquire_src_scope();
+        let barrier = self.image_barrier(
+            image, subresource_range, old_layout, new_layout,
+            src_access_mask, self.dst_access_mask);
+        unsafe {
+            device.cmd_pipeline_barrier(
+                cmd_buf,
+                src_stage_mask,
+                self.dst_stage_mask,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[barrier],
+            );
+        }
+    }
+}
+
+
+#[test]
+fn test_same_family_barrier()
+{
+    let transfer = QueueOwnershipTransfer {
+        src_queue_family_index: 0,
+        dst_queue_family_index: 0,
+        src_stage_mask: vk::PipelineStageFlags::TRANSFER,
+        src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+        dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+        dst_access_mask: vk::AccessFlags::SHADER_READ,
+    };
+    assert!(!transfer.is_needed());
+    assert_eq!(transfer.acquire_src_scope(), (vk::PipelineStageFlags::TRANSFER, vk::AccessFlags::TRANSFER_WRITE));
+    let barrier = transfer.image_barrier(
+        vk::Image::null(), vk::ImageSubresourceRange::default(),
+        vk::ImageLayout::TRANSFER_DST_OPTIMAL, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+        vk::AccessFlags::TRANSFER_WRITE, vk::AccessFlags::SHADER_READ);
+    assert_eq!(barrier.src_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+    assert_eq!(barrier.dst_queue_family_index, vk::QUEUE_FAMILY_IGNORED);
+    assert_eq!(barrier.new_layout, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL);
+}
@@ -0,0 +1,750 @@
+use ash::vk;
+use std::collections::HashMap;
+
+// 只解析生成pipeline layout和顶点输入需要的部分，不做完整的SPIR-V校验
+const SPIRV_MAGIC: u32 = 0x0723_0203;
+
+const OP_NAME: u32 = 5;
+const OP_ENTRY_POINT: u32 = 15;
+const OP_EXECUTION_MODE: u32 = 16;
+const OP_TYPE_BOOL: u32 = 20;
+const OP_TYPE_INT: u32 = 21;
+const OP_TYPE_FLOAT: u32 = 22;
+const OP_TYPE_VECTOR: u32 = 23;
+const OP_TYPE_MATRIX: u32 = 24;
+const OP_TYPE_IMAGE: u32 = 25;
+const OP_TYPE_SAMPLER: u32 = 26;
+const OP_TYPE_SAMPLED_IMAGE: u32 = 27;
+const OP_TYPE_ARRAY: u32 = 28;
+const OP_TYPE_RUNTIME_ARRAY: u32 = 29;
+const OP_TYPE_STRUCT: u32 = 30;
+const OP_TYPE_POINTER: u32 = 32;
+const OP_CONSTANT: u32 = 43;
+const OP_SPEC_CONSTANT: u32 = 50;
+const OP_VARIABLE: u32 = 59;
+const OP_DECORATE: u32 = 71;
+const OP_MEMBER_DECORATE: u32 = 72;
+
+const DECORATION_BUFFER_BLOCK: u32 = 3;
+const DECORATION_ARRAY_STRIDE: u32 = 6;
+const DECORATION_MATRIX_STRIDE: u32 = 7;
+const DECORATION_BUILT_IN: u32 = 11;
+const DECORATION_LOCATION: u32 = 30;
+const DECORATION_BINDING: u32 = 33;
+const DECORATION_DESCRIPTOR_SET: u32 = 34;
+const DECORATION_OFFSET: u32 = 35;
+
+const STORAGE_CLASS_UNIFORM_CONSTANT: u32 = 0;
+const STORAGE_CLASS_INPUT: u32 = 1;
+const STORAGE_CLASS_UNIFORM: u32 = 2;
+const STORAGE_CLASS_PUSH_CONSTANT: u32 = 9;
+const STORAGE_CLASS_STORAGE_BUFFER: u32 = 12;
+
+const EXECUTION_MODE_LOCAL_SIZE: u32 = 17;
+const DIM_BUFFER: u32 = 5;
+const DIM_SUBPASS_DATA: u32 = 6;
+
+// 顶点着色器的一个输入
+#[derive(Clone, Debug, PartialEq)]
+pub struct InputVariable {
+    pub name: String,
+    pub location: u32,
+    pub format: vk::Format,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct DescriptorBinding {
+    pub name: String,
+    pub set: u32,
+    pub binding: u32,
+    pub descriptor_type: vk::DescriptorType,
+    // 数组长度，运行时长度的数组为0
+    pub count: u32,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct ShaderReflection {
+    pub entry_point: String,
+    pub stage: vk::ShaderStageFlags,
+    // 按location排序，不包含内建变量
+    pub inputs: Vec<InputVariable>,
+    // 按(set, binding)排序
+    pub descriptor_bindings: Vec<DescriptorBinding>,
+    // stage_flags为本着色器的stage
+    pub push_constant_range: Option<vk::PushConstantRange>,
+    // compute着色器的local_size
+    pub workgroup_size: Option<[u32; 3]>,
+}
+
+#[derive(Clone, Debug)]
+enum SpirvType {
+    Bool,
+    Int { width: u32, signed: bool },
+    Float { width: u32 },
+    Vector { component: u32, count: u32 },
+    Matrix { column: u32, count: u32 },
+    Image { dim: u32, sampled: u32 },
+    Sampler,
+    SampledImage,
+    Array { element: u32, length: u32 },
+    RuntimeArray { element: u32 },
+    Struct { members: Vec<u32> },
+    Pointer { pointee: u32 },
+}
+
+#[derive(Default)]
+struct Module {
+    names: HashMap<u32, String>,
+    types: HashMap<u32, SpirvType>,
+    constants: HashMap<u32, u32>,
+    decorations: HashMap<(u32, u32), u32>,
+    member_decorations: HashMap<(u32, u32, u32), u32>,
+    flags: Vec<(u32, u32)>,
+    // (result id, pointer type id, storage class)
+    variables: Vec<(u32, u32, u32)>,
+}
+
+impl Module {
+    fn decoration(&self, id: u32, decoration: u32) -> Option<u32>
+    {
+        self.decorations.get(&(id, decoration)).cloned()
+    }
+
+    fn has_decoration(&self, id: u32, decoration: u32) -> bool
+    {
+        self.flags.contains(&(id, decoration)) || self.decorations.contains_key(&(id, decoration))
+    }
+
+    fn name(&self, id: u32) -> String
+    {
+        self.names.get(&id).cloned().unwrap_or_default()
+    }
+
+    fn get_type(&self, id: u32) -> Result<&SpirvType, String>
+    {
+        self.types.get(&id).ok_or(format!("unknown type id {}", id))
+    }
+
+    // 去掉数组，返回元素类型和元素个数
+    fn unwrap_array(&self, id: u32) -> Result<(u32, u32), String>
+    {
+        match self.get_type(id)? {
+            SpirvType::Array { element, length } => {
+                let (element, count) = self.unwrap_array(*element)?;
+                let length = *self.constants.get(length)
+                    .ok_or(format!("array length {} is not a constant", length))?;
+                Ok((element, count * length))
+            },
+            SpirvType::RuntimeArray { element } => Ok((*element, 0)),
+            _ => Ok((id, 1)),
+        }
+    }
+
+    // 一个输入占用的location数，数组和矩阵按元素/列展开，64位的3、4分量向量占2个
+    fn input_locations(&self, id: u32) -> u32
+    {
+        match self.types.get(&id) {
+            Some(SpirvType::Array { element, length }) =>
+                self.constants.get(length).cloned().unwrap_or(0) * self.input_locations(*element),
+            Some(SpirvType::Matrix { column, count }) => count * self.input_locations(*column),
+            Some(SpirvType::Vector { component, count }) => match self.types.get(component) {
+                Some(SpirvType::Float { width: 64 }) | Some(SpirvType::Int { width: 64, .. }) if *count > 2 => 2,
+                _ => 1,
+            },
+            _ => 1,
+        }
+    }
+
+    // 数组按元素、矩阵按列展开成多个输入，name[i]占用各自的location
+    // 没有对应顶点格式的类型跳过，不参与生成和校验
+    fn expand_input(&self, id: u32, name: String, location: u32, inputs: &mut Vec<InputVariable>)
+        -> Result<(), String>
+    {
+        let (element, count) = match self.get_type(id)? {
+            SpirvType::Array { element, length } => {
+                let length = *self.constants.get(length)
+                    .ok_or(format!("array length {} is not a constant", length))?;
+                (*element, length)
+            },
+            SpirvType::Matrix { column, count } => (*column, *count),
+            _ => {
+                if let Some(format) = self.input_format(id) {
+                    inputs.push(InputVariable { name, location, format });
+                }
+                return Ok(());
+            },
+        };
+        let stride = self.input_locations(element);
+        for index in 0..count {
+            self.expand_input(element, format!("{}[{}]", name, index), location + index * stride, inputs)?;
+        }
+        Ok(())
+    }
+
+    fn input_format(&self, id: u32) -> Option<vk::Format>
+    {
+        let (scalar, count) = match self.types.get(&id)? {
+            SpirvType::Vector { component, count } => (self.types.get(component)?, *count),
+            scalar => (scalar, 1),
+        };
+        let formats = match scalar {
+            SpirvType::Float { width: 16 } => [
+                vk::Format::R16_SFLOAT, vk::Format::R16G16_SFLOAT,
+                vk::Format::R16G16B16_SFLOAT, vk::Format::R16G16B16A16_SFLOAT,
+            ],
+            SpirvType::Float { width: 32 } => [
+                vk::Format::R32_SFLOAT, vk::Format::R32G32_SFLOAT,
+                vk::Format::R32G32B32_SFLOAT, vk::Format::R32G32B32A32_SFLOAT,
+            ],
+            SpirvType::Float { width: 64 } => [
+                vk::Format::R64_SFLOAT, vk::Format::R64G64_SFLOAT,
+                vk::Format::R64G64B64_SFLOAT, vk::Format::R64G64B64A64_SFLOAT,
+            ],
+            SpirvType::Int { width: 32, signed: true } => [
+                vk::Format::R32_SINT, vk::Format::R32G32_SINT,
+                vk::Format::R32G32B32_SINT, vk::Format::R32G32B32A32_SINT,
+            ],
+            SpirvType::Int { width: 32, signed: false } => [
+                vk::Format::R32_UINT, vk::Format::R32G32_UINT,
+                vk::Format::R32G32B32_UINT, vk::Format::R32G32B32A32_UINT,
+            ],
+            SpirvType::Int { width: 16, signed: true } => [
+                vk::Format::R16_SINT, vk::Format::R16G16_SINT,
+                vk::Format::R16G16B16_SINT, vk::Format::R16G16B16A16_SINT,
+            ],
+            SpirvType::Int { width: 16, signed: false } => [
+                vk::Format::R16_UINT, vk::Format::R16G16_UINT,
+                vk::Format::R16G16B16_UINT, vk::Format::R16G16B16A16_UINT,
+            ],
+            SpirvType::Int { width: 64, signed: true } => [
+                vk::Format::R64_SINT, vk::Format::R64G64_SINT,
+                vk::Format::R64G64B64_SINT, vk::Format::R64G64B64A64_SINT,
+            ],
+            SpirvType::Int { width: 64, signed: false } => [
+                vk::Format::R64_UINT, vk::Format::R64G64_UINT,
+                vk::Format::R64G64B64_UINT, vk::Format::R64G64B64A64_UINT,
+            ],
+            _ => return None,
+        };
+        formats.get((count as usize).checked_sub(1)?).cloned()
+    }
+
+    fn descriptor_type(&self, type_id: u32, storage_class: u32) -> Result<vk::DescriptorType, String>
+    {
+        let descriptor_type = match (storage_class, self.get_type(type_id)?) {
+            (STORAGE_CLASS_UNIFORM_CONSTANT, SpirvType::Sampler) => vk::DescriptorType::SAMPLER,
+            (STORAGE_CLASS_UNIFORM_CONSTANT, SpirvType::SampledImage) =>
+                vk::DescriptorType::COMBINED_IMAGE_SAMPLER,
+            (STORAGE_CLASS_UNIFORM_CONSTANT, SpirvType::Image { dim, sampled }) => match (*dim, *sampled) {
+                (DIM_BUFFER, 2) => vk::DescriptorType::STORAGE_TEXEL_BUFFER,
+                (DIM_BUFFER, _) => vk::DescriptorType::UNIFORM_TEXEL_BUFFER,
+                (DIM_SUBPASS_DATA, _) => vk::DescriptorType::INPUT_ATTACHMENT,
+                (_, 2) => vk::DescriptorType::STORAGE_IMAGE,
+                _ => vk::DescriptorType::SAMPLED_IMAGE,
+            },
+            (STORAGE_CLASS_UNIFORM, SpirvType::Struct { .. }) => {
+                // 旧版本GLSL编译出的storage buffer是Uniform + BufferBlock
+                if self.has_decoration(type_id, DECORATION_BUFFER_BLOCK) {
+                    vk::DescriptorType::STORAGE_BUFFER
+                } else {
+                    vk::DescriptorType::UNIFORM_BUFFER
+                }
+            },
+            (STORAGE_CLASS_STORAGE_BUFFER, SpirvType::Struct { .. }) => vk::DescriptorType::STORAGE_BUFFER,
+            (storage_class, other) =>
+                return Err(format!("unsupported resource {:?} in storage class {}", other, storage_class)),
+        };
+        Ok(descriptor_type)
+    }
+
+    // 按Offset/ArrayStride/MatrixStride装饰计算，用于push constant块
+    fn type_size(&self, id: u32) -> Result<u32, String>
+    {
+        let size = match self.get_type(id)? {
+            SpirvType::Bool => 4,
+            SpirvType::Int { width, .. } | SpirvType::Float { width } => width / 8,
+            SpirvType::Vector { component, count } => self.type_size(*component)? * count,
+            SpirvType::Matrix { column, count } => match self.decoration(id, DECORATION_MATRIX_STRIDE) {
+                Some(stride) => stride * count,
+                None => self.type_size(*column)? * count,
+            },
+            SpirvType::Array { element, length } => {
+                let length = *self.constants.get(length)
+                    .ok_or(format!("array length {} is not a constant", length))?;
+                let stride = match self.decoration(id, DECORATION_ARRAY_STRIDE) {
+                    Some(stride) => stride,
+                    None => self.type_size(*element)?,
+                };
+                stride * length
+            },
+            SpirvType::Struct { members } => {
+                let mut size = 0;
+                for (index, &member) in members.iter().enumerate() {
+                    let offset = self.member_decorations
+                        .get(&(id, index as u32, DECORATION_OFFSET))
+                        .cloned()
+                        .unwrap_or(0);
+                    // 成员上的MatrixStride
+                    let member_size = match (self.get_type(member)?, self.member_decorations
+                        .get(&(id, index as u32, DECORATION_MATRIX_STRIDE))) {
+                        (SpirvType::Matrix { count, .. }, Some(stride)) => stride * count,
+                        _ => self.type_size(member)?,
+                    };
+                    size = std::cmp::max(size, offset + member_size);
+                }
+                size
+            },
+            other => return Err(format!("type {:?} has no size", other)),
+        };
+        Ok(size)
+    }
+
+    // push constant块中第一个成员的偏移
+    fn struct_offset(&self, id: u32) -> u32
+    {
+        match self.types.get(&id) {
+            Some(SpirvType::Struct { members }) => (0..members.len() as u32)
+                .filter_map(|index| self.member_decorations.get(&(id, index, DECORATION_OFFSET)).cloned())
+                .min()
+                .unwrap_or(0),
+            _ => 0,
+        }
+    }
+}
+
+fn parse_string(words: &[u32]) -> (String, usize)
+{
+    let mut bytes = vec![];
+    for (index, word) in words.iter().enumerate() {
+        for &byte in word.to_le_bytes().iter() {
+            if byte == 0 {
+                return (String::from_utf8_lossy(&bytes).into_owned(), index + 1);
+            }
+            bytes.push(byte);
+        }
+    }
+    (String::from_utf8_lossy(&bytes).into_owned(), words.len())
+}
+
+fn execution_model_stage(execution_model: u32) -> vk::ShaderStageFlags
+{
+    match execution_model {
+        0 => vk::ShaderStageFlags::VERTEX,
+        1 => vk::ShaderStageFlags::TESSELLATION_CONTROL,
+        2 => vk::ShaderStageFlags::TESSELLATION_EVALUATION,
+        3 => vk::ShaderStageFlags::GEOMETRY,
+        4 => vk::ShaderStageFlags::FRAGMENT,
+        5 => vk::ShaderStageFlags::COMPUTE,
+        _ => vk::ShaderStageFlags::empty(),
+    }
+}
+
+// 解析第一个entry point，code为read_spv读出的SPIR-V
+pub fn reflect(code: &[u32]) -> Result<ShaderReflection, String>
+{
+    if code.len() < 5 || code[0] != SPIRV_MAGIC {
+        return Err("invalid spir-v header".to_string());
+    }
+    let mut module = Module::default();
+    let mut reflection = ShaderReflection::default();
+    let mut entry_id = None;
+    let mut pos = 5;
+    while pos < code.len() {
+        let word_count = (code[pos] >> 16) as usize;
+        let opcode = code[pos] & 0xffff;
+        if word_count == 0 || pos + word_count > code.len() {
+            return Err(format!("truncated instruction at word {}", pos));
+        }
+        let ops = &code[pos + 1..pos + word_count];
+        pos += word_count;
+        let op = |index: usize| ops.get(index).cloned().ok_or(format!("opcode {} is too short", opcode));
+        match opcode {
+            OP_NAME => {
+                module.names.insert(op(0)?, parse_string(&ops[1..]).0);
+            },
+            OP_ENTRY_POINT if entry_id.is_none() => {
+                reflection.stage = execution_model_stage(op(0)?);
+                entry_id = Some(op(1)?);
+                reflection.entry_point = parse_string(&ops[2..]).0;
+            },
+            OP_EXECUTION_MODE if Some(op(0)?) == entry_id && op(1)? == EXECUTION_MODE_LOCAL_SIZE => {
+                reflection.workgroup_size = Some([op(2)?, op(3)?, op(4)?]);
+            },
+            OP_TYPE_BOOL => {
+                module.types.insert(op(0)?, SpirvType::Bool);
+            },
+            OP_TYPE_INT => {
+                module.types.insert(op(0)?, SpirvType::Int { width: op(1)?, signed: op(2)? != 0 });
+            },
+            OP_TYPE_FLOAT => {
+                module.types.insert(op(0)?, SpirvType::Float { width: op(1)? });
+            },
+            OP_TYPE_VECTOR => {
+                module.types.insert(op(0)?, SpirvType::Vector { component: op(1)?, count: op(2)? });
+            },
+            OP_TYPE_MATRIX => {
+                module.types.insert(op(0)?, SpirvType::Matrix { column: op(1)?, count: op(2)? });
+            },
+            OP_TYPE_IMAGE => {
+                module.types.insert(op(0)?, SpirvType::Image { dim: op(2)?, sampled: op(6)? });
+            },
+            OP_TYPE_SAMPLER => {
+                module.types.insert(op(0)?, SpirvType::Sampler);
+            },
+            OP_TYPE_SAMPLED_IMAGE => {
+                module.types.insert(op(0)?, SpirvType::SampledImage);
+            },
+            OP_TYPE_ARRAY => {
+                module.types.insert(op(0)?, SpirvType::Array { element: op(1)?, length: op(2)? });
+            },
+            OP_TYPE_RUNTIME_ARRAY => {
+                module.types.insert(op(0)?, SpirvType::RuntimeArray { element: op(1)? });
+            },
+            OP_TYPE_STRUCT => {
+                module.types.insert(op(0)?, SpirvType::Struct { members: ops[1..].to_vec() });
+            },
+            OP_TYPE_POINTER => {
+                module.types.insert(op(0)?, SpirvType::Pointer { pointee: op(2)? });
+            },
+            // 只用于数组长度，取低32位；特化常量取默认值，pipeline特化成其他值时反射结果不反映
+            OP_CONSTANT | OP_SPEC_CONSTANT => {
+                module.constants.insert(op(1)?, op(2)?);
+            },
+            OP_VARIABLE => {
+                module.variables.push((op(1)?, op(0)?, op(2)?));
+            },
+            OP_DECORATE => match ops.get(2) {
+                Some(&value) => {
+                    module.decorations.insert((op(0)?, op(1)?), value);
+                },
+                None => module.flags.push((op(0)?, op(1)?)),
+            },
+            OP_MEMBER_DECORATE => {
+                if let Some(&value) = ops.get(3) {
+                    module.member_decorations.insert((op(0)?, op(1)?, op(2)?), value);
+                }
+            },
+            _ => {},
+        }
+    }
+    if entry_id.is_none() {
+        return Err("no entry point".to_string());
+    }
+
+    for &(id, pointer_type, storage_class) in module.variables.iter() {
+        let pointee = match module.get_type(pointer_type)? {
+            SpirvType::Pointer { pointee } => *pointee,
+            other => return Err(format!("variable {} has non-pointer type {:?}", id, other)),
+        };
+        match storage_class {
+            STORAGE_CLASS_INPUT if reflection.stage == vk::ShaderStageFlags::VERTEX => {
+                if module.has_decoration(id, DECORATION_BUILT_IN) {
+                    continue;
+                }
+                if let Some(location) = module.decoration(id, DECORATION_LOCATION) {
+                    module.expand_input(pointee, module.name(id), location, &mut reflection.inputs)?;
+                }
+            },
+            STORAGE_CLASS_UNIFORM_CONSTANT | STORAGE_CLASS_UNIFORM | STORAGE_CLASS_STORAGE_BUFFER => {
+                let (element, count) = module.unwrap_array(pointee)?;
+                let name = match module.name(id) {
+                    // 没有实例名的uniform块用块名
+                    name if name.is_empty() => module.name(element),
+                    name => name,
+                };
+                reflection.descriptor_bindings.push(DescriptorBinding {
+                    name,
+                    set: module.decoration(id, DECORATION_DESCRIPTOR_SET).unwrap_or(0),
+                    binding: module.decoration(id, DECORATION_BINDING).unwrap_or(0),
+                    descriptor_type: module.descriptor_type(element, storage_class)?,
+                    count,
+                });
+            },
+            STORAGE_CLASS_PUSH_CONSTANT => {
+                let offset = module.struct_offset(pointee);
+                reflection.push_constant_range = Some(vk::PushConstantRange {
+                    stage_flags: reflection.stage,
+                    offset,
+                    size: module.type_size(pointee)? - offset,
+                });
+            },
+            _ => {},
+        }
+    }
+    reflection.inputs.sort_by_key(|input| input.location);
+    reflection.descriptor_bindings.sort_by_key(|binding| (binding.set, binding.binding));
+    Ok(reflection)
+}
+
+// 格式的分量类型，用于比较顶点属性与着色器输入是否兼容
+fn format_numeric_type(format: vk::Format) -> Option<&'static str>
+{
+    match format {
+        vk::Format::R32_SFLOAT | vk::Format::R32G32_SFLOAT | vk::Format::R32G32B32_SFLOAT
+        | vk::Format::R32G32B32A32_SFLOAT | vk::Format::R16_SFLOAT | vk::Format::R16G16_SFLOAT
+        | vk::Format::R16G16B16_SFLOAT | vk::Format::R16G16B16A16_SFLOAT | vk::Format::R8G8B8A8_UNORM | vk::Format::B8G8R8A8_UNORM
+        | vk::Format::R8G8B8A8_SNORM | vk::Format::R16G16_UNORM | vk::Format::R16G16B16A16_UNORM
+        | vk::Format::R16G16_SNORM | vk::Format::R16G16B16A16_SNORM
+        | vk::Format::A2B10G10R10_UNORM_PACK32 => Some("float"),
+        vk::Format::R64_SFLOAT | vk::Format::R64G64_SFLOAT | vk::Format::R64G64B64_SFLOAT
+        | vk::Format::R64G64B64A64_SFLOAT => Some("double"),
+        vk::Format::R32_SINT | vk::Format::R32G32_SINT | vk::Format::R32G32B32_SINT
+        | vk::Format::R32G32B32A32_SINT | vk::Format::R16_SINT | vk::Format::R16G16_SINT
+        | vk::Format::R16G16B16_SINT | vk::Format::R16G16B16A16_SINT
+        | vk::Format::R8G8B8A8_SINT => Some("int"),
+        vk::Format::R32_UINT | vk::Format::R32G32_UINT | vk::Format::R32G32B32_UINT
+        | vk::Format::R32G32B32A32_UINT | vk::Format::R16_UINT | vk::Format::R16G16_UINT
+        | vk::Format::R16G16B16_UINT | vk::Format::R16G16B16A16_UINT
+        | vk::Format::R8G8B8A8_UINT => Some("uint"),
+        vk::Format::R64_SINT | vk::Format::R64G64_SINT | vk::Format::R64G64B64_SINT
+        | vk::Format::R64G64B64A64_SINT => Some("int64"),
+        vk::Format::R64_UINT | vk::Format::R64G64_UINT | vk::Format::R64G64B64_UINT
+        | vk::Format::R64G64B64A64_UINT => Some("uint64"),
+        _ => None,
+    }
+}
+
+fn format_size(format: vk::Format) -> u32
+{
+    match format {
+        vk::Format::R16_SFLOAT | vk::Format::R16_SINT | vk::Format::R16_UINT => 2,
+        vk::Format::R32_SFLOAT | vk::Format::R32_SINT | vk::Format::R32_UINT
+        | vk::Format::R16G16_SFLOAT | vk::Format::R16G16_SINT | vk::Format::R16G16_UINT => 4,
+        vk::Format::R16G16B16_SFLOAT | vk::Format::R16G16B16_SINT | vk::Format::R16G16B16_UINT => 6,
+        vk::Format::R32G32_SFLOAT | vk::Format::R32G32_SINT | vk::Format::R32G32_UINT
+        | vk::Format::R16G16B16A16_SFLOAT | vk::Format::R16G16B16A16_SINT | vk::Format::R16G16B16A16_UINT
+        | vk::Format::R64_SFLOAT | vk::Format::R64_SINT | vk::Format::R64_UINT => 8,
+        vk::Format::R32G32B32_SFLOAT | vk::Format::R32G32B32_SINT | vk::Format::R32G32B32_UINT => 12,
+        vk::Format::R32G32B32A32_SFLOAT | vk::Format::R32G32B32A32_SINT | vk::Format::R32G32B32A32_UINT
+        | vk::Format::R64G64_SFLOAT | vk::Format::R64G64_SINT | vk::Format::R64G64_UINT => 16,
+        vk::Format::R64G64B64_SFLOAT | vk::Format::R64G64B64_SINT | vk::Format::R64G64B64_UINT => 24,
+        vk::Format::R64G64B64A64_SFLOAT | vk::Format::R64G64B64A64_SINT
+        | vk::Format::R64G64B64A64_UINT => 32,
+        _ => 0,
+    }
+}
+
+// 所有输入紧密排列在binding 0
+pub fn vertex_input_desc(reflection: &ShaderReflection)
+    -> (Vec<vk::VertexInputBindingDescription>, Vec<vk::VertexInputAttributeDescription>)
+{
+    let mut offset = 0;
+    let attr_desc = reflection.inputs
+        .iter()
+        .map(|input| {
+            let attr = vk::VertexInputAttributeDescription {
+                location: input.location,
+                binding: 0,
+                format: input.format,
+                offset,
+            };
+            offset += format_size(input.format);
+            attr
+        })
+        .collect::<Vec<vk::VertexInputAttributeDescription>>();
+    let binding_desc = match attr_desc.len() {
+        0 => vec![],
+        _ => vec![vk::VertexInputBindingDescription {
+            binding: 0,
+            stride: offset,
+            input_rate: vk::VertexInputRate::VERTEX,
+        }],
+    };
+    (binding_desc, attr_desc)
+}
+
+// 每个输入都要有location相同、分量类型一致的属性
+pub fn validate_vertex_input(reflection: &ShaderReflection,
+                             attr_desc: &[vk::VertexInputAttributeDescription])
+    -> Result<(), String>
+{
+    for input in reflection.inputs.iter() {
+        let attr = attr_desc.iter()
+            .find(|attr| attr.location == input.location)
+            .ok_or(format!("vertex input {} (location {}) has no attribute", input.name, input.location))?;
+        if format_numeric_type(attr.format) != format_numeric_type(input.format) {
+            return Err(format!(
+                "vertex input {} (location {}) is {:?}, attribute format is {:?}",
+                input.name, input.location, input.format, attr.format,
+            ));
+        }
+    }
+    Ok(())
+}
+
+// 合并各stage的binding，同一位置类型不同时报错
+pub fn merge_descriptor_bindings(reflections: &[&ShaderReflection])
+    -> Result<Vec<Vec<vk::DescriptorSetLayoutBinding>>, String>
+{
+    let mut sets: Vec<Vec<vk::DescriptorSetLayoutBinding>> = vec![];
+    for reflection in reflections.iter() {
+        for binding in reflection.descriptor_bindings.iter() {
+            while sets.len() <= binding.set as usize {
+                sets.push(vec![]);
+            }
+            let set = &mut sets[binding.set as usize];
+            match set.iter_mut().find(|layout_binding| layout_binding.binding == binding.binding) {
+                Some(layout_binding) => {
+                    if layout_binding.descriptor_type != binding.descriptor_type {
+                        return Err(format!(
+                            "set {} binding {} is {:?} in {:?} but {:?} in another stage",
+                            binding.set, binding.binding, binding.descriptor_type, reflection.stage,
+                            layout_binding.descriptor_type,
+                        ));
+                    }
+                    layout_binding.stage_flags |= reflection.stage;
+                },
+                None => set.push(vk::DescriptorSetLayoutBinding {
+                    binding: binding.binding,
+                    descriptor_type: binding.descriptor_type,
+                    // 运行时长度的数组按1个处理
+                    descriptor_count: std::cmp::max(binding.count, 1),
+                    stage_flags: reflection.stage,
+                    ..Default::default()
+                }),
+            }
+        }
+    }
+    Ok(sets)
+}
+
+// 着色器用到的binding都要在layout中声明，且类型一致、数量足够、stage可见
+pub fn validate_descriptor_bindings(reflection: &ShaderReflection,
+                                    set_layouts: &[Vec<vk::DescriptorSetLayoutBinding>])
+    -> Result<(), String>
+{
+    for binding in reflection.descriptor_bindings.iter() {
+        let layout_binding = set_layouts.get(binding.set as usize)
+            .and_then(|set| set.iter().find(|layout_binding| layout_binding.binding == binding.binding))
+            .ok_or(format!(
+                "{} (set {} binding {}) is not declared in the descriptor set layouts",
+                binding.name, binding.set, binding.binding,
+            ))?;
+        if layout_binding.descriptor_type != binding.descriptor_type {
+            return Err(format!(
+                "{} (set {} binding {}) is {:?} in shader but {:?} in layout",
+                binding.name, binding.set, binding.binding, binding.descriptor_type,
+                layout_binding.descriptor_type,
+            ));
+        }
+        if layout_binding.descriptor_count < binding.count {
+            return Err(format!(
+                "{} (set {} binding {}) needs {} descriptors but layout has {}",
+                binding.name, binding.set, binding.binding, binding.count, layout_binding.descriptor_count,
+            ));
+        }
+        if !layout_binding.stage_flags.contains(reflection.stage) {
+            return Err(format!(
+                "{} (set {} binding {}) is not visible to {:?}",
+                binding.name, binding.set, binding.binding, reflection.stage,
+            ));
+        }
+    }
+    Ok(())
+}
+
+// 着色器使用的push constant要被声明了本stage的range完整覆盖
+pub fn validate_push_constants(reflection: &ShaderReflection, ranges: &[vk::PushConstantRange])
+    -> Result<(), String>
+{
+    let used = match reflection.push_constant_range {
+        Some(used) => used,
+        None => return Ok(()),
+    };
+    let covered = ranges.iter().any(|range| {
+        range.stage_flags.contains(reflection.stage)
+            && range.offset <= used.offset
+            && used.offset + used.size <= range.offset + range.size
+    });
+    if !covered {
+        return Err(format!(
+            "push constants [{}, {}) of {:?} are not covered by the declared ranges",
+            used.offset, used.offset + used.size, reflection.stage,
+        ));
+    }
+    Ok(())
+}
+
+
+#[test]
+fn test_reflect_triangle()
+{
+    let bytes = std::fs::read("./tests/data/triangle.vert.spv").unwrap();
+    let code = ash::util::read_spv(&mut std::io::Cursor::new(bytes)).unwrap();
+    let reflection = reflect(&code).unwrap();
+    assert_eq!(reflection.entry_point, "main");
+    assert_eq!(reflection.stage, vk::ShaderStageFlags::VERTEX);
+    let formats = reflection.inputs.iter().map(|input| (input.location, input.format)).collect::<Vec<_>>();
+    assert_eq!(formats, vec![(0, vk::Format::R32G32_SFLOAT), (1, vk::Format::R32G32B32_SFLOAT)]);
+    assert!(reflection.descriptor_bindings.is_empty());
+
+    let (binding_desc, attr_desc) = vertex_input_desc(&reflection);
+    assert_eq!(binding_desc[0].stride, 20);
+    assert_eq!(attr_desc[1].offset, 8);
+    assert!(validate_vertex_input(&reflection, &attr_desc).is_ok());
+    let wrong = [vk::VertexInputAttributeDescription {
+        location: 0,
+        format: vk::Format::R32G32_UINT,
+        ..Default::default()
+    }];
+    assert!(validate_vertex_input(&reflection, &wrong).is_err());
+
+    let fragment = ShaderReflection {
+        stage: vk::ShaderStageFlags::FRAGMENT,
+        descriptor_bindings: vec![DescriptorBinding {
+            name: "tex".to_string(),
+            set: 1,
+            binding: 0,
+            descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+            count: 1,
+        }],
+        ..Default::default()
+    };
+    let sets = merge_descriptor_bindings(&[&reflection, &fragment]).unwrap();
+    assert_eq!(sets.len(), 2);
+    assert!(sets[0].is_empty());
+    assert!(validate_descriptor_bindings(&fragment, &sets).is_ok());
+    assert!(validate_descriptor_bindings(&fragment, &sets[..1]).is_err());
+}
+
+#[test]
+fn test_reflect_matrix_input()
+{
+    // layout(constant_id = 0) const int N = 2;
+    // layout(location = 2) in mat4 model; layout(location = 6) in float weights[N];
+    let inst = |opcode: u32, operands: &[u32]| {
+        let mut words = vec![((operands.len() as u32 + 1) << 16) | opcode];
+        words.extend_from_slice(operands);
+        words
+    };
+    let main = u32::from_le_bytes(*b"main");
+    let mut code = vec![SPIRV_MAGIC, 0x0001_0000, 0, 16, 0];
+    code.extend(inst(OP_ENTRY_POINT, &[0, 1, main, 0, 10, 11]));
+    code.extend(inst(OP_NAME, &[10, u32::from_le_bytes(*b"mode"), u32::from_le_bytes(*b"l\0\0\0")]));
+    code.extend(inst(OP_DECORATE, &[10, DECORATION_LOCATION, 2]));
+    code.extend(inst(OP_DECORATE, &[11, DECORATION_LOCATION, 6]));
+    code.extend(inst(OP_TYPE_FLOAT, &[2, 32]));
+    code.extend(inst(OP_TYPE_VECTOR, &[3, 2, 4]));
+    code.extend(inst(OP_TYPE_MATRIX, &[4, 3, 4]));
+    code.extend(inst(OP_TYPE_POINTER, &[5, STORAGE_CLASS_INPUT, 4]));
+    code.extend(inst(OP_TYPE_INT, &[6, 32, 0]));
+    code.extend(inst(OP_SPEC_CONSTANT, &[6, 7, 2]));
+    code.extend(inst(OP_TYPE_ARRAY, &[8, 2, 7]));
+    code.extend(inst(OP_TYPE_POINTER, &[9, STORAGE_CLASS_INPUT, 8]));
+    code.extend(inst(OP_VARIABLE, &[5, 10, STORAGE_CLASS_INPUT]));
+    code.extend(inst(OP_VARIABLE, &[9, 11, STORAGE_CLASS_INPUT]));
+
+    let reflection = reflect(&code).unwrap();
+    let inputs = reflection.inputs.iter().map(|input| (input.location, input.format)).collect::<Vec<_>>();
+    assert_eq!(inputs, vec![
+        (2, vk::Format::R32G32B32A32_SFLOAT),
+        (3, vk::Format::R32G32B32A32_SFLOAT),
+        (4, vk::Format::R32G32B32A32_SFLOAT),
+        (5, vk::Format::R32G32B32A32_SFLOAT),
+        (6, vk::Format::R32_SFLOAT),
+        (7, vk::Format::R32_SFLOAT),
+    ]);
+    assert_eq!(reflection.inputs[1].name, "model[1]");
+    let (binding_desc, _) = vertex_input_desc(&reflection);
+    assert_eq!(binding_desc[0].stride, 72);
+}
@@ -0,0 +1,378 @@
+use ash::vk;
+use ash::version::*;
+use super::ri;
+use super::pso;
+use super::buffer::DeviceBuffer;
+use super::utility::{find_memorytype_index, submit_one_time_commands};
+use super::error::{Error, Result};
+
+// 回读时是否需要交换R、B通道，不支持回读的格式返回None
+fn read_back_swizzle(format: vk::Format) -> Option<bool>
+{
+    match format {
+        vk::Format::R8G8B8A8_UNORM
+        | vk::Format::R8G8B8A8_SRGB => Some(false),
+        vk::Format::B8G8R8A8_UNORM
+        | vk::Format::B8G8R8A8_SRGB => Some(true),
+        _ => None,
+    }
+}
+
+// 离屏渲染目标：持有color/depth图像和framebuffer，可以回读color图像
+pub struct RenderTarget {
+    pub extent: vk::Extent2D,
+    pub color_format: vk::Format,
+    pub color_image: vk::Image,
+    pub color_memory: vk::DeviceMemory,
+    pub color_view: vk::ImageView,
+    // render pass结束后color图像所处的layout
+    pub color_layout: vk::ImageLayout,
+    pub depth_format: vk::Format,
+    pub depth_image: vk::Image,
+    pub depth_memory: vk::DeviceMemory,
+    pub depth_view: vk::ImageView,
+    pub frame_buffer: vk::Framebuffer,
+    device: ash::Device,
+}
+
+impl RenderTarget {
+    // attachment_desc[0]为color，attachment_desc[1]（可选）为depth，与create_pipeline_state_object一致
+    pub fn new(backend: &ri::Backend, pso_obj: &pso::PipelineStateObject, extent: vk::Extent2D)
+        -> Result<Self>
+    {
+        let device = &backend.device;
+        let device_memory_properties = unsafe {
+            backend.instance.get_physical_device_memory_properties(backend.physical_device)
+        };
+        let attachment_desc = &pso_obj.pso_desc.attachment_desc;
+        if attachment_desc.is_empty() {
+            return Err(Error::NoColorAttachment(pso_obj.pso_desc.name.clone()));
+        }
+
+        let color_format = attachment_desc[0].format;
+        // 只支持能回读为RGBA8的格式
+        if read_back_swizzle(color_format).is_none() {
+            return Err(Error::UnsupportedFormat(color_format));
+        }
+        let color_layout = attachment_desc[0].final_layout;
+        let depth_format = attachment_desc
+            .get(1)
+            .map_or(vk::Format::UNDEFINED, |desc| desc.format);
+
+        // 先构造空的RenderTarget，中途失败时由Drop释放已创建的对象
+        let mut target = RenderTarget {
+            extent,
+            color_format,
+            color_image: vk::Image::null(),
+            color_memory: vk::DeviceMemory::null(),
+            color_view: vk::ImageView::null(),
+            color_layout,
+            depth_format,
+            depth_image: vk::Image::null(),
+            depth_memory: vk::DeviceMemory::null(),
+            depth_view: vk::ImageView::null(),
+            frame_buffer: vk::Framebuffer::null(),
+            device: device.clone(),
+        };
+        let (color_image, color_memory, color_view) = RenderTarget::create_attachment(
+            device,
+            &device_memory_properties,
+            extent,
+            color_format,
+            vk::ImageUsageFlags::COLOR_ATTACHMENT
+                | vk::ImageUsageFlags::SAMPLED
+                | vk::ImageUsageFlags::TRANSFER_SRC,
+            vk::ImageAspectFlags::COLOR,
+        )?;
+        target.color_image = color_image;
+        target.color_memory = color_memory;
+        target.color_view = color_view;
+
+        if depth_format != vk::Format::UNDEFINED {
+            let (depth_image, depth_memory, depth_view) = RenderTarget::create_attachment(
+                device,
+                &device_memory_properties,
+                extent,
+                depth_format,
+                vk::ImageUsageFlags::DEPTH_STENCIL_ATTACHMENT,
+                vk::ImageAspectFlags::DEPTH,
+            )?;
+            target.depth_image = depth_image;
+            target.depth_memory = depth_memory;
+            target.depth_view = depth_view;
+        }
+
+        target.frame_buffer = {
+            let framebuffer_attachments = match depth_format {
+                vk::Format::UNDEFINED => vec![target.color_view],
+                _ => vec![target.color_view, target.depth_view],
+            };
+            let fb_ci = vk::FramebufferCreateInfo::builder()
+                .render_pass(pso_obj.render_pass)
+                .attachments(&framebuffer_attachments)
+                .width(extent.width)
+                .height(extent.height)
+                .layers(1);
+            unsafe {
+                device.create_framebuffer(&fb_ci, None)?
+            }
+        };
+
+        let name = &pso_obj.pso_desc.name;
+        backend.set_object_name(target.color_image, &format!("{}_color", name));
+        backend.set_object_name(target.color_view, &format!("{}_color_view", name));
+        if depth_format != vk::Format::UNDEFINED {
+            backend.set_object_name(target.depth_image, &format!("{}_depth", name));
+            backend.set_object_name(target.depth_view, &format!("{}_depth_view", name));
+        }
+        backend.set_object_name(target.frame_buffer, &format!("{}_frame_buffer", name));
+
+        Ok(target)
+    }
+
+    fn create_attachment(device: &ash::Device,
+                         device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+                         extent: vk::Extent2D,
+                         format: vk::Format,
+                         usage: vk::ImageUsageFlags,
+                         aspect_mask: vk::ImageAspectFlags)
+        -> Result<(vk::Image, vk::DeviceMemory, vk::ImageView)>
+    {
+        let image_ci = vk::ImageCreateInfo {
+            image_type: vk::ImageType::TYPE_2D,
+            format,
+            extent: vk::Extent3D {
+                width: extent.width,
+                height: extent.height,
+                depth: 1,
+            },
+            mip_levels: 1,
+            array_layers: 1,
+            samples: vk::SampleCountFlags::TYPE_1,
+            tiling: vk::ImageTiling::OPTIMAL,
+            usage,
+            sharing_mode: vk::SharingMode::EXCLUSIVE,
+            ..Default::default()
+        };
+        let image = unsafe {
+            device.create_image(&image_ci, None)?
+        };
+        // 后续步骤失败时释放已创建的对象
+        let memory = match RenderTarget::allocate_image_memory(device, device_memory_properties, image) {
+            Ok(memory) => memory,
+            Err(e) => {
+                unsafe {
+                    device.destroy_image(image, None);
+                }
+                return Err(e);
+            },
+        };
+        let image_view_ci = vk::ImageViewCreateInfo {
+            view_type: vk::ImageViewType::TYPE_2D,
+            image,
+            format,
+            subresource_range: vk::ImageSubresourceRange {
+                aspect_mask,
+                base_mip_level: 0,
+                level_count: 1,
+                base_array_layer: 0,
+                layer_count: 1,
+            },
+            ..Default::default()
+        };
+        let image_view = match unsafe { device.create_image_view(&image_view_ci, None) } {
+            Ok(image_view) => image_view,
+            Err(e) => {
+                unsafe {
+                    device.destroy_image(image, None);
+                    device.free_memory(memory, None);
+                }
+                return Err(e.into());
+            },
+        };
+        Ok((image, memory, image_view))
+    }
+
+    // 分配device local内存并绑定到image，绑定失败时释放内存
+    fn allocate_image_memory(device: &ash::Device,
+                             device_memory_properties: &vk::PhysicalDeviceMemoryProperties,
+                             image: vk::Image)
+        -> Result<vk::DeviceMemory>
+    {
+        unsafe {
+            let memory_req = device.get_image_memory_requirements(image);
+            let memory_allocate_ci = vk::MemoryAllocateInfo {
+                allocation_size: memory_req.size,
+                memory_type_index: find_memorytype_index(
+                    &memory_req,
+                    device_memory_properties,
+                    vk::MemoryPropertyFlags::DEVICE_LOCAL,
+                ).ok_or(Error::NoSuitableMemoryType(vk::MemoryPropertyFlags::DEVICE_LOCAL))?,
+                ..Default::default()
+            };
+            let memory = device.allocate_memory(&memory_allocate_ci, None)?;
+            if let Err(e) = device.bind_image_memory(image, memory, 0) {
+                device.free_memory(memory, None);
+                return Err(e.into());
+            }
+            Ok(memory)
+        }
+    }
+
+    // 把color图像拷贝到host可见的buffer，返回按行紧密排列的RGBA8数据
+    pub fn read_back(&self, backend: &ri::Backend, cmd_pool: vk::CommandPool, queue: vk::Queue)
+        -> Result<Vec<u8>>
+    {
+        let swizzle_bgra = read_back_swizzle(self.color_format)
+            .ok_or(Error::UnsupportedFormat(self.color_format))?;
+        let pixel_count = (self.extent.width * self.extent.height) as usize;
+        let device_memory_properties = unsafe {
+            backend.instance.get_physical_device_memory_properties(backend.physical_device)
+        };
+        let staging_buffer_ci = vk::BufferCreateInfo::builder()
+            .size((pixel_count * 4) as u64)
+            .usage(vk::BufferUsageFlags::TRANSFER_DST)
+            .sharing_mode(vk::SharingMode::EXCLUSIVE)
+            .build();
+        let staging_buffer = DeviceBuffer::new(
+            &backend.device,
+            &device_memory_properties,
+            &staging_buffer_ci,
+            vk::MemoryPropertyFlags::HOST_VISIBLE | vk::MemoryPropertyFlags::HOST_COHERENT,
+        )?;
+
+        let subresource_range = vk::ImageSubresourceRange {
+            aspect_mask: vk::ImageAspectFlags::COLOR,
+            base_mip_level: 0,
+            level_count: 1,
+            base_array_layer: 0,
+            layer_count: 1,
+        };
+        submit_one_time_commands(&backend.device, cmd_pool, queue, |device, cmd_buf| unsafe {
+            let to_transfer = vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                dst_access_mask: vk::AccessFlags::TRANSFER_READ,
+                old_layout: self.color_layout,
+                new_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: self.color_image,
+                subresource_range,
+                ..Default::default()
+            };
+            device.cmd_pipeline_barrier(
+                cmd_buf,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_transfer],
+            );
+            let region = vk::BufferImageCopy {
+                buffer_offset: 0,
+                buffer_row_length: 0,
+                buffer_image_height: 0,
+                image_subresource: vk::ImageSubresourceLayers {
+                    aspect_mask: vk::ImageAspectFlags::COLOR,
+                    mip_level: 0,
+                    base_array_layer: 0,
+                    layer_count: 1,
+                },
+                image_offset: vk::Offset3D { x: 0, y: 0, z: 0 },
+                image_extent: vk::Extent3D {
+                    width: self.extent.width,
+                    height: self.extent.height,
+                    depth: 1,
+                },
+            };
+            device.cmd_copy_image_to_buffer(
+                cmd_buf,
+                self.color_image,
+                vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                staging_buffer.buffer,
+                &[region],
+            );
+            // 拷贝完成后host才能读取staging buffer
+            let to_host = vk::BufferMemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_WRITE,
+                dst_access_mask: vk::AccessFlags::HOST_READ,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                buffer: staging_buffer.buffer,
+                offset: 0,
+                size: vk::WHOLE_SIZE,
+                ..Default::default()
+            };
+            device.cmd_pipeline_barrier(
+                cmd_buf,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::HOST,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[to_host],
+                &[],
+            );
+            // 恢复原layout，不影响后续渲染
+            let to_attachment = vk::ImageMemoryBarrier {
+                src_access_mask: vk::AccessFlags::TRANSFER_READ,
+                dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+                old_layout: vk::ImageLayout::TRANSFER_SRC_OPTIMAL,
+                new_layout: self.color_layout,
+                src_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                dst_queue_family_index: vk::QUEUE_FAMILY_IGNORED,
+                image: self.color_image,
+                subresource_range,
+                ..Default::default()
+            };
+            device.cmd_pipeline_barrier(
+                cmd_buf,
+                vk::PipelineStageFlags::TRANSFER,
+                vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+                vk::DependencyFlags::empty(),
+                &[],
+                &[],
+                &[to_attachment],
+            );
+        })?;
+
+        let mut pixels = staging_buffer.as_bytes()[..pixel_count * 4].to_vec();
+        if swizzle_bgra {
+            for pixel in pixels.chunks_mut(4) {
+                pixel.swap(0, 2);
+            }
+        }
+        Ok(pixels)
+    }
+
+    pub fn save_png(&self, backend: &ri::Backend, cmd_pool: vk::CommandPool, queue: vk::Queue,
+                    path: &str)
+        -> Result<()>
+    {
+        let pixels = self.read_back(backend, cmd_pool, queue)?;
+        image::save_buffer(
+            path,
+            &pixels,
+            self.extent.width,
+            self.extent.height,
+            image::RGBA(8),
+        )?;
+        Ok(())
+    }
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_framebuffer(self.frame_buffer, None);
+            self.device.destroy_image_view(self.color_view, None);
+            self.device.destroy_image(self.color_image, None);
+            self.device.free_memory(self.color_memory, None);
+            if self.depth_format != vk::Format::UNDEFINED {
+                self.device.destroy_image_view(self.depth_view, None);
+                self.device.destroy_image(self.depth_image, None);
+                self.device.free_memory(self.depth_memory, None);
+            }
+        }
+    }
+}
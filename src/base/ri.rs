@@ -8,238 +8,353 @@ use ash::version::*;
 use ash::vk;
 use std::ffi::{CString, CStr};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::error::{Error, Result};
+use super::device_selector::{DeviceSelector, DeviceRequirements, PhysicalDeviceInfo};
+use super::validation::{self, ValidationConfig};
+use super::debug;
 
 pub struct Backend {
     pub entry: ash::Entry, // vulkan函数入口
     pub instance: ash::Instance,
     pub debug_utils: ext::DebugUtils,
-    pub debug_callback: vk::DebugUtilsMessengerEXT,
+    pub debug_callback: vk::DebugUtilsMessengerEXT, // 没有开启验证时为null
+    // 验证层是否实际开启
+    pub validation_enabled: bool,
+    // debug messenger收到的ERROR数量，Box保证地址不变（作为callback的user_data）
+    validation_errors: Box<AtomicUsize>,
     pub physical_device: vk::PhysicalDevice,
+    pub physical_device_info: PhysicalDeviceInfo,
     pub surface_khr: vk::SurfaceKHR,
     pub surface: khr::Surface,
     pub queue_family_index: u32,
+    pub compute_queue_family_index: u32,
+    pub transfer_queue_family_index: u32,
     pub device: ash::Device,
 }
 
 
 
 impl Backend {
-    pub fn new(window: &winit::Window, select_gpu_idx: usize) -> Self
+    pub fn new(window: &winit::Window, selector: &DeviceSelector, validation: &ValidationConfig)
+        -> Result<Self>
     {
-        let entry = ash::Entry::new().unwrap();
-        let instance = {
-            let app_name = CString::new("rt_vt_exp").unwrap();
-            let layer_names = [CString::new("VK_LAYER_KHRONOS_validation").unwrap()];
-            let layer_names_raw = layer_names
-                .iter()
-                .map(|raw_name| raw_name.as_ptr())
-                .collect::<Vec<*const i8>>();
-
-            let extension_name_raw;
-            let mut surface_extensions = ash_window::enumerate_required_extensions(window).unwrap();
-            surface_extensions.push(&ext::DebugUtils::name());
-            extension_name_raw = surface_extensions.iter()
-                .map(|ext| ext.as_ptr())
-                .collect::<Vec<*const i8>>();
-
-            let app_info = vk::ApplicationInfo::builder()
-                .application_name(&app_name)
-                .application_version(0)
-                .engine_name(&app_name)
-                .engine_version(0)
-                .api_version(vk::make_version(1, 0, 0));
-
-            let create_info = vk::InstanceCreateInfo::builder()
-                .application_info(&app_info)
-                .enabled_layer_names(&layer_names_raw)
-                .enabled_extension_names(&extension_name_raw);
-
-            unsafe {
-                entry.create_instance(&create_info, None)
-                    .expect("Instance creation error")
-            }
-        };
-        let debug_utils = ext::DebugUtils::new(&entry, &instance);
-        let debug_callback = {
-            let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
-                .message_severity(
-                    vk::DebugUtilsMessageSeverityFlagsEXT::ERROR |
-                        vk::DebugUtilsMessageSeverityFlagsEXT::WARNING |
-                        vk::DebugUtilsMessageSeverityFlagsEXT::INFO
-                )
-                .message_type(
-                    vk::DebugUtilsMessageTypeFlagsEXT::all()
-                )
-                .pfn_user_callback(Some(Backend::vulkan_debug_callback));
-
-            unsafe {
-                debug_utils.create_debug_utils_messenger(&debug_info, None)
-                    .unwrap()
-            }
-        };
-        let physical_devices = unsafe {
-            let _p_d = instance.enumerate_physical_devices()
-                .expect("Physical device error");
-            assert!(_p_d.len() > 0, "Get physical device number is zero");
-            _p_d
-        };
-        let physical_device = {
-            assert!(select_gpu_idx < physical_devices.len(),
-                    format!("Select physical device is error. sum is {}, select is {}",
-                        physical_devices.len(), select_gpu_idx)
-            );
-            physical_devices[select_gpu_idx]
+        let entry = ash::Entry::new()?;
+        let api_version = Backend::instance_api_version(&entry)?;
+        let (instance, validation_enabled) = {
+            let mut extension_names = ash_window::enumerate_required_extensions(window)?;
+            extension_names.push(ext::DebugUtils::name());
+            Backend::create_instance(&entry, api_version, &extension_names, validation)?
         };
+        let validation_errors = Box::new(AtomicUsize::new(0));
+        let (debug_utils, debug_callback) = Backend::create_debug_callback(
+            &entry, &instance, validation_enabled, validation, &validation_errors)?;
         let surface_khr = unsafe {
             ash_window::create_surface(
                 &entry, &instance, window, None
-            ).unwrap()
+            )?
         };
         let surface = khr::Surface::new(&entry, &instance);
+        let mut requirements = selector.requirements.clone();
+        let swapchain_name = khr::Swapchain::name().to_owned();
+        if !requirements.extensions.contains(&swapchain_name) {
+            requirements.extensions.push(swapchain_name);
+        }
+        let physical_device_info = DeviceSelector {
+            requirements: requirements.clone(),
+            preference: selector.preference.clone(),
+        }.select(&instance, api_version, Some((&surface, surface_khr)))?;
+        let physical_device = physical_device_info.physical_device;
         let graphic_queue_family_index = unsafe {
-            instance.get_physical_device_queue_family_properties(physical_device)
-                .iter()
-                .enumerate()
-                .filter_map(|(index, ref info)|{
-                    let supports_graphic_and_surface =
-                        info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
-                            && surface
-                                .get_physical_device_surface_support(
-                                    physical_device,
-                                    index as u32,
-                                    surface_khr,
-                                )
-                                .unwrap();
-                    if supports_graphic_and_surface {
-                        Some(index)
-                    } else {
-                        None
-                    }
-                })
-                .next()
-                .unwrap()
-                as u32
-        };
-
-        let device = {
-            let device_extension_names_raw = [khr::Swapchain::name().as_ptr()];
-            let features = vk::PhysicalDeviceFeatures {
-                shader_clip_distance: 1,
-                ..Default::default()
-            };
-            let priorities = [1.0];
-            let queue_create_infos = [vk::DeviceQueueCreateInfo::builder()
-                .queue_family_index(graphic_queue_family_index)
-                .queue_priorities(&priorities)
-                .build(),
-            ];
-            let device_create_info = vk::DeviceCreateInfo::builder()
-                .queue_create_infos(&queue_create_infos)
-                .enabled_extension_names(&device_extension_names_raw)
-                .enabled_features(&features);
-            unsafe {
-                instance
-                    .create_device(physical_device, &device_create_info, None)
-                    .unwrap()
+            let mut graphic_index = None;
+            for (index, info) in physical_device_info.queue_families.iter().enumerate() {
+                let supports_graphic_and_surface =
+                    info.queue_flags.contains(vk::QueueFlags::GRAPHICS)
+                        && surface
+                            .get_physical_device_surface_support(
+                                physical_device,
+                                index as u32,
+                                surface_khr,
+                            )?;
+                if supports_graphic_and_surface {
+                    graphic_index = Some(index as u32);
+                    break;
+                }
             }
+            graphic_index.ok_or(Error::NoQueueFamily(vk::QueueFlags::GRAPHICS))?
         };
+        let (compute_queue_family_index, transfer_queue_family_index) =
+            select_async_queue_families(
+                &physical_device_info.queue_families, graphic_queue_family_index);
+        let device = Backend::create_device(
+            &instance,
+            physical_device,
+            &[graphic_queue_family_index, compute_queue_family_index, transfer_queue_family_index],
+            &requirements,
+        )?;
 
-        Backend {
+        Ok(Backend {
             entry,
             instance,
             debug_utils,
             debug_callback,
+            validation_enabled,
+            validation_errors,
             physical_device,
+            physical_device_info,
             surface_khr,
             surface,
             queue_family_index: graphic_queue_family_index,
+            compute_queue_family_index,
+            transfer_queue_family_index,
             device
-        }
+        })
     }
 
-    unsafe extern "system" fn vulkan_debug_callback(
-        message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
-        message_type: vk::DebugUtilsMessageTypeFlagsEXT,
-        p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
-        _user_data: *mut std::os::raw::c_void,
-    ) -> vk::Bool32 {
-        let callback_data = *p_callback_data;
-        let message_id_number = callback_data.message_id_number;
-        let message_id_name = CStr::from_ptr(callback_data.p_message_id_name).to_string_lossy();
-        let message = CStr::from_ptr(callback_data.p_message).to_string_lossy();
+    // 无窗口模式：不创建surface，不开启swapchain扩展，用于没有显示设备的环境（如CI）
+    pub fn new_headless(selector: &DeviceSelector, validation: &ValidationConfig) -> Result<Self>
+    {
+        let entry = ash::Entry::new()?;
+        let api_version = Backend::instance_api_version(&entry)?;
+        let (instance, validation_enabled) = Backend::create_instance(
+            &entry, api_version, &[ext::DebugUtils::name()], validation)?;
+        let validation_errors = Box::new(AtomicUsize::new(0));
+        let (debug_utils, debug_callback) = Backend::create_debug_callback(
+            &entry, &instance, validation_enabled, validation, &validation_errors)?;
+        let physical_device_info = selector.select(&instance, api_version, None)?;
+        let physical_device = physical_device_info.physical_device;
+        let surface = khr::Surface::new(&entry, &instance);
+        let graphic_queue_family_index = physical_device_info.queue_families
+            .iter()
+            .position(|info| info.queue_flags.contains(vk::QueueFlags::GRAPHICS))
+            .ok_or(Error::NoQueueFamily(vk::QueueFlags::GRAPHICS))?
+            as u32;
+        let (compute_queue_family_index, transfer_queue_family_index) =
+            select_async_queue_families(
+                &physical_device_info.queue_families, graphic_queue_family_index);
+        let device = Backend::create_device(
+            &instance,
+            physical_device,
+            &[graphic_queue_family_index, compute_queue_family_index, transfer_queue_family_index],
+            &selector.requirements,
+        )?;
+
+        Ok(Backend {
+            entry,
+            instance,
+            debug_utils,
+            debug_callback,
+            validation_enabled,
+            validation_errors,
+            physical_device,
+            physical_device_info,
+            surface_khr: vk::SurfaceKHR::null(),
+            surface,
+            queue_family_index: graphic_queue_family_index,
+            compute_queue_family_index,
+            transfer_queue_family_index,
+            device
+        })
+    }
 
-        println!(
-            "{:?}:{:?} [{}:{}] :\n{}\n",
-            message_severity,
-            message_type,
-            &message_id_number.to_string(),
-            message_id_name,
-            message
-        );
+    pub fn is_headless(&self) -> bool
+    {
+        self.surface_khr == vk::SurfaceKHR::null()
+    }
 
-        return vk::FALSE;
+    // loader支持时使用Vulkan 1.1（用于查询device UUID等），否则为1.0
+    fn instance_api_version(entry: &ash::Entry) -> Result<u32>
+    {
+        let version_1_1 = vk::make_version(1, 1, 0);
+        Ok(match entry.try_enumerate_instance_version()? {
+            Some(version) if version >= version_1_1 => version_1_1,
+            _ => vk::make_version(1, 0, 0),
+        })
     }
 
-    pub fn get_queue_family_index(&self, flags: vk::QueueFlags)
-        -> u32
+    // 返回instance和验证层是否实际开启
+    fn create_instance(entry: &ash::Entry, api_version: u32, extension_names: &[&CStr],
+                       validation: &ValidationConfig)
+        -> Result<(ash::Instance, bool)>
     {
-        let props = unsafe {
-            self.instance.get_physical_device_queue_family_properties(self.physical_device)
-        };
-        let f_get_queue =
-            |support_flags: vk::QueueFlags| {
-                props.iter()
-                    .enumerate()
-                    .filter_map(|(idx, prop)| {
-                        let prop_flags = &prop.queue_flags;
-                        match prop_flags.contains(support_flags) {
-                            true => Some(idx),
-                            false => None,
-                        }
-                    })
-                    .next()
+        let app_name = CString::new("rt_vt_exp").unwrap();
+        let validation_layer = CString::new("VK_LAYER_KHRONOS_validation").unwrap();
+        // 没有安装验证层的机器（如只有软件ICD的CI）上跳过验证层
+        let has_validation_layer = entry.enumerate_instance_layer_properties()?
+            .iter()
+            .any(|prop| unsafe {
+                CStr::from_ptr(prop.layer_name.as_ptr()) == validation_layer.as_c_str()
+            });
+        let validation_enabled = validation.enabled && has_validation_layer;
+        if validation.enabled && !has_validation_layer {
+            log::warn!("VK_LAYER_KHRONOS_validation not found, validation disabled");
+        }
+        let layer_names_raw = if validation_enabled {
+            vec![validation_layer.as_ptr()]
+        } else {
+            vec![]
         };
-        if flags.contains(vk::QueueFlags::COMPUTE) {
-            let queue_idx = {
-                let support_flags = vk::QueueFlags::COMPUTE;
-                // let ignore_flags = vk::QueueFlags::GRAPHICS & vk::QueueFlags::TRANSFER;
-                f_get_queue(support_flags)
-            };
-            if queue_idx.is_some() {
-                return queue_idx.unwrap() as u32;
-            }
+        // VK_EXT_validation_features由验证层提供
+        let use_validation_features = validation_enabled && validation.needs_validation_features();
+        let mut extension_name_raw = extension_names.iter()
+            .map(|ext| ext.as_ptr())
+            .collect::<Vec<*const i8>>();
+        if use_validation_features {
+            extension_name_raw.push(vk::ExtValidationFeaturesFn::name().as_ptr());
         }
-        else if flags.contains(vk::QueueFlags::TRANSFER) {
-            let queue_idx = {
-                let support_flags = vk::QueueFlags::TRANSFER;
-                // let ignore_flags = vk::QueueFlags::GRAPHICS & vk::QueueFlags::COMPUTE;
-                f_get_queue(support_flags)
-            };
-            if queue_idx.is_some() {
-                return queue_idx.unwrap() as u32;
-            }
+        let enabled_validation_features = validation.enabled_validation_features();
+        let mut validation_features = vk::ValidationFeaturesEXT::builder()
+            .enabled_validation_features(&enabled_validation_features);
+
+        let app_info = vk::ApplicationInfo::builder()
+            .application_name(&app_name)
+            .application_version(0)
+            .engine_name(&app_name)
+            .engine_version(0)
+            .api_version(api_version);
+
+        let mut create_info = vk::InstanceCreateInfo::builder()
+            .application_info(&app_info)
+            .enabled_layer_names(&layer_names_raw)
+            .enabled_extension_names(&extension_name_raw);
+        if use_validation_features {
+            create_info = create_info.push_next(&mut validation_features);
         }
-        else if flags.contains(vk::QueueFlags::GRAPHICS) {
-            let queue_idx = {
-                let support_flags = vk::QueueFlags::GRAPHICS;
-                // let ignore_flags = vk::QueueFlags::COMPUTE & vk::QueueFlags::TRANSFER;
-                f_get_queue(support_flags)
-            };
-            if queue_idx.is_some() {
-                return queue_idx.unwrap() as u32;
-            }
+
+        let instance = unsafe {
+            entry.create_instance(&create_info, None)?
+        };
+        Ok((instance, validation_enabled))
+    }
+
+    fn create_debug_callback(entry: &ash::Entry, instance: &ash::Instance,
+                             validation_enabled: bool, validation: &ValidationConfig,
+                             validation_errors: &AtomicUsize)
+        -> Result<(ext::DebugUtils, vk::DebugUtilsMessengerEXT)>
+    {
+        let debug_utils = ext::DebugUtils::new(entry, instance);
+        if !validation_enabled {
+            return Ok((debug_utils, vk::DebugUtilsMessengerEXT::null()));
+        }
+        let debug_info = vk::DebugUtilsMessengerCreateInfoEXT::builder()
+            .message_severity(validation.message_severity)
+            .message_type(validation.message_type)
+            .pfn_user_callback(Some(validation::vulkan_debug_callback))
+            .user_data(validation_errors as *const AtomicUsize as *mut std::os::raw::c_void);
+
+        let debug_callback = unsafe {
+            debug_utils.create_debug_utils_messenger(&debug_info, None)?
+        };
+        Ok((debug_utils, debug_callback))
+    }
+
+    fn create_device(instance: &ash::Instance, physical_device: vk::PhysicalDevice,
+                     queue_family_indices: &[u32], requirements: &DeviceRequirements)
+        -> Result<ash::Device>
+    {
+        let device_extension_names_raw = requirements.extensions.iter()
+            .map(|ext| ext.as_ptr())
+            .collect::<Vec<*const i8>>();
+        let features = requirements.features;
+        let priorities = [1.0];
+        // 每个队列族只能创建一次
+        let mut unique_indices = queue_family_indices.to_vec();
+        unique_indices.sort();
+        unique_indices.dedup();
+        let queue_create_infos = unique_indices.iter()
+            .map(|&queue_family_index| {
+                vk::DeviceQueueCreateInfo::builder()
+                    .queue_family_index(queue_family_index)
+                    .queue_priorities(&priorities)
+                    .build()
+            })
+            .collect::<Vec<vk::DeviceQueueCreateInfo>>();
+        let device_create_info = vk::DeviceCreateInfo::builder()
+            .queue_create_infos(&queue_create_infos)
+            .enabled_extension_names(&device_extension_names_raw)
+            .enabled_features(&features);
+        unsafe {
+            Ok(instance.create_device(physical_device, &device_create_info, None)?)
         }
+    }
+
+    pub fn set_object_name<T: vk::Handle>(&self, handle: T, name: &str)
+    {
+        debug::set_object_name(&self.debug_utils, self.device.handle(), handle, name);
+    }
+
+    // 返回的CmdLabel离开作用域时结束标签区域
+    pub fn cmd_label(&self, cmd_buf: vk::CommandBuffer, name: &str) -> debug::CmdLabel<'_>
+    {
+        debug::CmdLabel::new(&self.debug_utils, cmd_buf, name, [0.0, 0.0, 0.0, 0.0])
+    }
+
+    // 验证层报告的错误数量，测试结束时可以断言为0
+    pub fn validation_error_count(&self) -> usize
+    {
+        self.validation_errors.load(Ordering::SeqCst)
+    }
+
+    // 返回App实际使用的队列族，没有专用队列族时会回退到graphic队列族
+    pub fn get_queue_family_index(&self, flags: vk::QueueFlags)
+        -> Result<u32>
+    {
         if flags.contains(vk::QueueFlags::GRAPHICS) {
-            let queue_idx = {
-                let support_flags = vk::QueueFlags::GRAPHICS;
-                f_get_queue(support_flags)
-            };
-            if queue_idx.is_some() {
-                return queue_idx.unwrap() as u32;
-            }
+            Ok(self.queue_family_index)
+        } else if flags.contains(vk::QueueFlags::COMPUTE) {
+            Ok(self.compute_queue_family_index)
+        } else if flags.contains(vk::QueueFlags::TRANSFER) {
+            Ok(self.transfer_queue_family_index)
+        } else {
+            Err(Error::NoQueueFamily(flags))
         }
-        panic!("wrong queue flags");
     }
+
+    pub fn has_dedicated_compute_queue(&self) -> bool
+    {
+        self.compute_queue_family_index != self.queue_family_index
+    }
+
+    pub fn has_dedicated_transfer_queue(&self) -> bool
+    {
+        self.transfer_queue_family_index != self.queue_family_index
+    }
+}
+
+// 优先选择支持flags且不含ignore_flags的队列族，找不到时放宽条件
+pub fn find_queue_family_index(props: &[vk::QueueFamilyProperties],
+                               flags: vk::QueueFlags, ignore_flags: vk::QueueFlags)
+    -> Option<u32>
+{
+    let f_get_queue = |ignore_flags: vk::QueueFlags| {
+        props.iter()
+            .position(|prop| {
+                prop.queue_count > 0
+                    && prop.queue_flags.contains(flags)
+                    && !prop.queue_flags.intersects(ignore_flags)
+            })
+            .map(|idx| idx as u32)
+    };
+    f_get_queue(ignore_flags)
+        .or_else(|| f_get_queue(ignore_flags & vk::QueueFlags::GRAPHICS))
+        .or_else(|| f_get_queue(vk::QueueFlags::empty()))
+}
+
+// 选择异步compute和transfer队列族，没有专用队列族时回退到graphic队列族
+pub fn select_async_queue_families(props: &[vk::QueueFamilyProperties], graphic_index: u32)
+    -> (u32, u32)
+{
+    let compute_index = find_queue_family_index(
+        props,
+        vk::QueueFlags::COMPUTE,
+        vk::QueueFlags::GRAPHICS,
+    ).unwrap_or(graphic_index);
+    let transfer_index = find_queue_family_index(
+        props,
+        vk::QueueFlags::TRANSFER,
+        vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE,
+    ).unwrap_or(graphic_index);
+    (compute_index, transfer_index)
 }
 
 impl Drop for Backend {
@@ -247,11 +362,34 @@ impl Drop for Backend {
         unsafe {
             self.device.device_wait_idle().unwrap();
             self.device.destroy_device(None);
-            self.debug_utils.destroy_debug_utils_messenger(self.debug_callback, None);
+            if !self.is_headless() {
+                self.surface.destroy_surface(self.surface_khr, None);
+            }
+            if self.debug_callback != vk::DebugUtilsMessengerEXT::null() {
+                self.debug_utils.destroy_debug_utils_messenger(self.debug_callback, None);
+            }
             self.instance.destroy_instance(None);
-            self.surface.destroy_surface(self.surface_khr, None)
         }
     }
 }
 
 
+
+
+#[test]
+fn test_select_async_queue_families()
+{
+    let family = |queue_flags: vk::QueueFlags| vk::QueueFamilyProperties {
+        queue_flags,
+        queue_count: 1,
+        ..Default::default()
+    };
+    let props = [
+        family(vk::QueueFlags::GRAPHICS | vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER),
+        family(vk::QueueFlags::COMPUTE | vk::QueueFlags::TRANSFER),
+        family(vk::QueueFlags::TRANSFER),
+    ];
+    assert_eq!(select_async_queue_families(&props, 0), (1, 2));
+    assert_eq!(select_async_queue_families(&props[..2], 0), (1, 1));
+    assert_eq!(select_async_queue_families(&props[..1], 0), (0, 0));
+}
@@ -0,0 +1,401 @@
+use directx_math::*;
+
+// 局部变换，按 缩放 -> 旋转 -> 平移 的顺序作用
+#[derive(Clone, Copy, Debug)]
+pub struct Transform {
+    pub translation: XMFLOAT3,
+    // 单位四元数(x, y, z, w)
+    pub rotation: XMFLOAT4,
+    pub scale: XMFLOAT3,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            translation: XMFLOAT3 { x: 0.0, y: 0.0, z: 0.0 },
+            rotation: XMFLOAT4 { x: 0.0, y: 0.0, z: 0.0, w: 1.0 },
+            scale: XMFLOAT3 { x: 1.0, y: 1.0, z: 1.0 },
+        }
+    }
+}
+
+impl Transform {
+    pub fn from_translation(x: f32, y: f32, z: f32) -> Self
+    {
+        Transform {
+            translation: XMFLOAT3 { x, y, z },
+            ..Default::default()
+        }
+    }
+
+    // 含错切的矩阵无法分解，返回None
+    pub fn from_matrix(m: FXMMATRIX) -> Option<Self>
+    {
+        let mut scale = XMVectorZero();
+        let mut rotation = XMVectorZero();
+        let mut translation = XMVectorZero();
+        if !XMMatrixDecompose(&mut scale, &mut rotation, &mut translation, m) {
+            return None;
+        }
+        let mut transform = Transform::default();
+        XMStoreFloat3(&mut transform.scale, scale);
+        XMStoreFloat4(&mut transform.rotation, rotation);
+        XMStoreFloat3(&mut transform.translation, translation);
+        Some(transform)
+    }
+
+    pub fn set_uniform_scale(&mut self, scale: f32)
+    {
+        self.scale = XMFLOAT3 { x: scale, y: scale, z: scale };
+    }
+
+    // 弧度
+    pub fn set_rotation_euler(&mut self, pitch: f32, yaw: f32, roll: f32)
+    {
+        XMStoreFloat4(&mut self.rotation, XMQuaternionRotationRollPitchYaw(pitch, yaw, roll));
+    }
+
+    // 在现有旋转之后绕父空间的axis再旋转angle弧度
+    pub fn rotate(&mut self, axis: FXMVECTOR, angle: f32)
+    {
+        let delta = XMQuaternionRotationAxis(axis, angle);
+        let rotation = XMQuaternionNormalize(XMQuaternionMultiply(XMLoadFloat4(&self.rotation), delta));
+        XMStoreFloat4(&mut self.rotation, rotation);
+    }
+
+    pub fn translate(&mut self, offset: FXMVECTOR)
+    {
+        let translation = XMVectorAdd(XMLoadFloat3(&self.translation), offset);
+        XMStoreFloat3(&mut self.translation, translation);
+    }
+
+    pub fn matrix(&self) -> XMMATRIX
+    {
+        XMMatrixAffineTransformation(
+            XMLoadFloat3(&self.scale),
+            XMVectorZero(),
+            XMLoadFloat4(&self.rotation),
+            XMLoadFloat3(&self.translation),
+        )
+    }
+
+    // 平移和缩放线性插值，旋转球面插值，可配合FrameClock::alpha做固定步长插值
+    pub fn lerp(&self, other: &Transform, t: f32) -> Transform
+    {
+        let mut transform = Transform::default();
+        XMStoreFloat3(&mut transform.translation, XMVectorLerp(
+            XMLoadFloat3(&self.translation), XMLoadFloat3(&other.translation), t));
+        XMStoreFloat4(&mut transform.rotation, XMQuaternionSlerp(
+            XMLoadFloat4(&self.rotation), XMLoadFloat4(&other.rotation), t));
+        XMStoreFloat3(&mut transform.scale, XMVectorLerp(
+            XMLoadFloat3(&self.scale), XMLoadFloat3(&other.scale), t));
+        transform
+    }
+}
+
+// generation在节点删除时递增，删除后保留的NodeId不会指向复用该位置的新节点
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct NodeId {
+    index: usize,
+    generation: u32,
+}
+
+struct Slot<T> {
+    generation: u32,
+    node: Option<Node<T>>,
+}
+
+// renderable为渲染需要的数据（mesh、材质等），为None的节点只参与变换
+pub struct Node<T> {
+    pub name: String,
+    pub renderable: Option<T>,
+    transform: Transform,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    // 缓存的世界矩阵，Scene::update之后有效
+    world: XMFLOAT4X4,
+    dirty: bool,
+}
+
+impl<T> Node<T> {
+    pub fn transform(&self) -> &Transform
+    {
+        &self.transform
+    }
+
+    pub fn parent(&self) -> Option<NodeId>
+    {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[NodeId]
+    {
+        &self.children
+    }
+
+    pub fn world_matrix(&self) -> XMMATRIX
+    {
+        XMLoadFloat4x4(&self.world)
+    }
+}
+
+// 节点保存在Vec中，用NodeId引用，删除后的位置会被复用
+// 修改局部变换只标记当前节点，update时从根节点向下重新计算，父节点变化的子树一并更新
+pub struct Scene<T> {
+    nodes: Vec<Slot<T>>,
+    free_list: Vec<usize>,
+    roots: Vec<NodeId>,
+}
+
+impl<T> Default for Scene<T> {
+    fn default() -> Self {
+        Scene {
+            nodes: vec![],
+            free_list: vec![],
+            roots: vec![],
+        }
+    }
+}
+
+impl<T> Scene<T> {
+    pub fn new() -> Self
+    {
+        Scene::default()
+    }
+
+    pub fn add_node(&mut self, name: &str, parent: Option<NodeId>, transform: Transform) -> NodeId
+    {
+        let parent = parent.filter(|&parent| self.node(parent).is_some());
+        let node = Node {
+            name: name.to_string(),
+            renderable: None,
+            transform,
+            parent,
+            children: vec![],
+            world: XMFLOAT4X4::default(),
+            dirty: true,
+        };
+        let id = match self.free_list.pop() {
+            Some(index) => {
+                let slot = &mut self.nodes[index];
+                slot.node = Some(node);
+                NodeId { index, generation: slot.generation }
+            },
+            None => {
+                self.nodes.push(Slot { generation: 0, node: Some(node) });
+                NodeId { index: self.nodes.len() - 1, generation: 0 }
+            },
+        };
+        match parent {
+            Some(parent) => self.node_mut(parent).unwrap().children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    pub fn add_renderable(&mut self, name: &str, parent: Option<NodeId>, transform: Transform,
+                          renderable: T) -> NodeId
+    {
+        let id = self.add_node(name, parent, transform);
+        self.node_mut(id).unwrap().renderable = Some(renderable);
+        id
+    }
+
+    // 删除节点及其所有子节点
+    pub fn remove_node(&mut self, id: NodeId)
+    {
+        let parent = match self.node(id) {
+            Some(node) => node.parent,
+            None => return,
+        };
+        self.detach(id, parent);
+        let mut stack = vec![id];
+        while let Some(id) = stack.pop() {
+            let slot = &mut self.nodes[id.index];
+            if let Some(node) = slot.node.take() {
+                slot.generation = slot.generation.wrapping_add(1);
+                stack.extend(node.children);
+                self.free_list.push(id.index);
+            }
+        }
+    }
+
+    // parent为None时变成根节点，parent是id自身或其子孙时返回false
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> bool
+    {
+        let old_parent = match self.node(id) {
+            Some(node) => node.parent,
+            None => return false,
+        };
+        if let Some(parent) = parent {
+            if self.node(parent).is_none() || self.is_ancestor(id, parent) {
+                return false;
+            }
+        }
+        self.detach(id, old_parent);
+        match parent {
+            Some(parent) => self.node_mut(parent).unwrap().children.push(id),
+            None => self.roots.push(id),
+        }
+        let node = self.node_mut(id).unwrap();
+        node.parent = parent;
+        node.dirty = true;
+        true
+    }
+
+    pub fn node(&self, id: NodeId) -> Option<&Node<T>>
+    {
+        self.nodes
+            .get(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_ref())
+    }
+
+    pub fn node_mut(&mut self, id: NodeId) -> Option<&mut Node<T>>
+    {
+        self.nodes
+            .get_mut(id.index)
+            .filter(|slot| slot.generation == id.generation)
+            .and_then(|slot| slot.node.as_mut())
+    }
+
+    pub fn find(&self, name: &str) -> Option<NodeId>
+    {
+        self.iter().find(|(_, node)| node.name == name).map(|(id, _)| id)
+    }
+
+    pub fn roots(&self) -> &[NodeId]
+    {
+        &self.roots
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (NodeId, &Node<T>)>
+    {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(index, slot)| {
+                let id = NodeId { index, generation: slot.generation };
+                slot.node.as_ref().map(|node| (id, node))
+            })
+    }
+
+    // 修改局部变换并标记为dirty
+    pub fn transform_mut(&mut self, id: NodeId) -> Option<&mut Transform>
+    {
+        self.node_mut(id).map(|node| {
+            node.dirty = true;
+            &mut node.transform
+        })
+    }
+
+    pub fn set_transform(&mut self, id: NodeId, transform: Transform)
+    {
+        if let Some(node_transform) = self.transform_mut(id) {
+            *node_transform = transform;
+        }
+    }
+
+    // 重新计算dirty节点及其子树的世界矩阵，每帧渲染前调用
+    pub fn update(&mut self)
+    {
+        let mut stack = self.roots
+            .iter()
+            .map(|&root| (root, XMMatrixIdentity(), false))
+            .collect::<Vec<(NodeId, XMMATRIX, bool)>>();
+        while let Some((id, parent_world, parent_changed)) = stack.pop() {
+            let node = self.nodes[id.index].node.as_mut().unwrap();
+            let changed = parent_changed || node.dirty;
+            if changed {
+                let world = XMMatrixMultiply(node.transform.matrix(), &parent_world);
+                XMStoreFloat4x4(&mut node.world, world);
+                node.dirty = false;
+            }
+            let world = XMLoadFloat4x4(&node.world);
+            stack.extend(node.children.iter().map(|&child| (child, world, changed)));
+        }
+    }
+
+    // 带renderable的节点及其世界矩阵，需要先调用update
+    pub fn renderables(&self) -> impl Iterator<Item = (NodeId, XMMATRIX, &T)>
+    {
+        self.iter().filter_map(|(id, node)| {
+            node.renderable.as_ref().map(|renderable| (id, node.world_matrix(), renderable))
+        })
+    }
+
+    fn detach(&mut self, id: NodeId, parent: Option<NodeId>)
+    {
+        let siblings = match parent {
+            Some(parent) => &mut self.node_mut(parent).unwrap().children,
+            None => &mut self.roots,
+        };
+        siblings.retain(|&sibling| sibling != id);
+    }
+
+    // ancestor是否为node自身或其祖先
+    fn is_ancestor(&self, ancestor: NodeId, node: NodeId) -> bool
+    {
+        let mut current = Some(node);
+        while let Some(id) = current {
+            if id == ancestor {
+                return true;
+            }
+            current = self.node(id).and_then(|node| node.parent);
+        }
+        false
+    }
+}
+
+
+#[test]
+fn test_scene_world_matrix()
+{
+    let mut scene = Scene::new();
+    let root = scene.add_node("root", None, Transform::from_translation(1.0, 0.0, 0.0));
+    let mut child_transform = Transform::from_translation(0.0, 2.0, 0.0);
+    child_transform.set_uniform_scale(2.0);
+    let child = scene.add_renderable("child", Some(root), child_transform, "cube");
+    scene.update();
+
+    let origin = |scene: &Scene<&str>, id: NodeId| {
+        let mut position = XMFLOAT3::default();
+        XMStoreFloat3(&mut position, XMVector3TransformCoord(
+            XMVectorZero(), scene.node(id).unwrap().world_matrix()));
+        position
+    };
+    let position = origin(&scene, child);
+    assert!((position.x - 1.0).abs() < 1e-5 && (position.y - 2.0).abs() < 1e-5);
+
+    // 父节点旋转后子节点跟随
+    scene.transform_mut(root).unwrap().rotate(XMVectorSet(0.0, 0.0, 1.0, 0.0), XM_PIDIV2);
+    scene.update();
+    let position = origin(&scene, child);
+    assert!((position.x + 1.0).abs() < 1e-5 && position.y.abs() < 1e-5);
+    let decomposed = Transform::from_matrix(scene.node(child).unwrap().world_matrix()).unwrap();
+    assert!((decomposed.scale.x - 2.0).abs() < 1e-5);
+
+    assert!(!scene.set_parent(root, Some(child)));
+    assert_eq!(scene.renderables().map(|(id, _, name)| (id, *name)).collect::<Vec<_>>(),
+               vec![(child, "cube")]);
+    scene.remove_node(root);
+    assert!(scene.node(child).is_none() && scene.roots().is_empty());
+}
+
+#[test]
+fn test_scene_stale_node_id()
+{
+    let mut scene: Scene<()> = Scene::new();
+    let removed = scene.add_node("removed", None, Transform::default());
+    scene.remove_node(removed);
+    // 复用同一位置的新节点不能通过旧的NodeId访问
+    let reused = scene.add_node("reused", None, Transform::default());
+    assert_eq!(reused.index, removed.index);
+    assert!(scene.node(removed).is_none());
+    assert!(scene.transform_mut(removed).is_none());
+    assert!(!scene.set_parent(removed, None));
+    let child = scene.add_node("child", Some(removed), Transform::default());
+    assert_eq!(scene.node(child).unwrap().parent(), None);
+    scene.remove_node(removed);
+    assert_eq!(scene.node(reused).unwrap().name, "reused");
+}
@@ -10,10 +10,111 @@ use std::ffi::CString;
 use super::utility;
 use std::boxed::Box;
 use super::buffer;
+use super::error::Result;
+
+// swapchain配置，各列表按优先级排列
+#[derive(Clone, Debug)]
+pub struct SwapchainConfig {
+    // 都不支持时回退到FIFO（所有实现都必须支持）
+    pub present_modes: Vec<vk::PresentModeKHR>,
+    // 期望的图像数量，会被限制在surface支持的范围内
+    pub image_count: u32,
+    // 格式和色彩空间都匹配才算支持，都不支持时使用驱动上报的第一个格式
+    pub surface_formats: Vec<vk::SurfaceFormatKHR>,
+}
+
+impl Default for SwapchainConfig {
+    fn default() -> Self {
+        SwapchainConfig {
+            present_modes: vec![vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::FIFO],
+            image_count: 3,
+            surface_formats: SwapchainConfig::unorm_formats(),
+        }
+    }
+}
+
+impl SwapchainConfig {
+    pub fn srgb_formats() -> Vec<vk::SurfaceFormatKHR> {
+        vec![
+            vk::SurfaceFormatKHR {
+                format: vk::Format::B8G8R8A8_SRGB,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+            vk::SurfaceFormatKHR {
+                format: vk::Format::R8G8B8A8_SRGB,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+        ]
+    }
+
+    pub fn unorm_formats() -> Vec<vk::SurfaceFormatKHR> {
+        vec![
+            vk::SurfaceFormatKHR {
+                format: vk::Format::B8G8R8A8_UNORM,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+            vk::SurfaceFormatKHR {
+                format: vk::Format::R8G8B8A8_UNORM,
+                color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+            },
+        ]
+    }
+}
+
+// 实际创建出的swapchain参数
+#[derive(Clone, Copy, Debug)]
+pub struct SwapchainInfo {
+    pub surface_format: vk::SurfaceFormatKHR,
+    pub present_mode: vk::PresentModeKHR,
+    pub image_count: u32,
+    pub extent: vk::Extent2D,
+}
+
+pub fn choose_present_mode(supported: &[vk::PresentModeKHR], preferred: &[vk::PresentModeKHR])
+    -> vk::PresentModeKHR
+{
+    preferred
+        .iter()
+        .cloned()
+        .find(|mode| supported.contains(mode))
+        .unwrap_or(vk::PresentModeKHR::FIFO)
+}
+
+pub fn choose_surface_format(supported: &[vk::SurfaceFormatKHR], preferred: &[vk::SurfaceFormatKHR])
+    -> vk::SurfaceFormatKHR
+{
+    let default_format = vk::SurfaceFormatKHR {
+        format: vk::Format::B8G8R8A8_UNORM,
+        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    };
+    // 只上报一个UNDEFINED表示可以使用任意格式
+    if supported.len() == 1 && supported[0].format == vk::Format::UNDEFINED {
+        return preferred.first().cloned().unwrap_or(default_format);
+    }
+    preferred
+        .iter()
+        .cloned()
+        .find(|pref| supported.iter().any(|sfmt|
+            sfmt.format == pref.format && sfmt.color_space == pref.color_space))
+        .or_else(|| supported.first().cloned())
+        .unwrap_or(default_format)
+}
+
+// max_image_count为0表示没有上限
+pub fn choose_image_count(capabilities: &vk::SurfaceCapabilitiesKHR, desired: u32) -> u32
+{
+    let count = std::cmp::max(desired, capabilities.min_image_count);
+    match capabilities.max_image_count {
+        0 => count,
+        max_count => std::cmp::min(count, max_count),
+    }
+}
 
 pub struct Surface {
     pub surface_format: vk::SurfaceFormatKHR,
     pub surface_resolution: vk::Extent2D,
+    pub present_mode: vk::PresentModeKHR,
+    pub config: SwapchainConfig,
     pub swapchain: khr::Swapchain,
     pub swapchain_khr: vk::SwapchainKHR,
     pub present_images: Vec<vk::Image>,
@@ -24,106 +125,20 @@ pub struct Surface {
 }
 
 impl Surface {
-    pub fn new(backend: Rc<Backend>, window: &winit::Window)
-               -> Self
+    pub fn new(backend: Rc<Backend>, window: &winit::Window, config: &SwapchainConfig)
+               -> Result<Self>
     {
-        let surface = &backend.surface;
         let surface_khr = backend.surface_khr.clone();
+        // 格式决定了surface pso的render pass，重建swapchain时不再改变
         let surface_format = {
             let surface_formats = unsafe {
                 backend.surface
-                    .get_physical_device_surface_formats(backend.physical_device, surface_khr)
-                    .unwrap()
+                    .get_physical_device_surface_formats(backend.physical_device, surface_khr)?
             };
-            surface_formats
-                .iter()
-                .map(|sfmt| match sfmt.format {
-                    vk::Format::UNDEFINED => vk::SurfaceFormatKHR {
-                        format: vk::Format::R8G8B8A8_SNORM,
-                        color_space: sfmt.color_space,
-                    },
-                    _ => *sfmt
-                })
-                .next()
-                .unwrap()
+            choose_surface_format(&surface_formats, &config.surface_formats)
         };
-        let surface_resolution = vk::Extent2D { width: 800, height: 600 };
+        let surface_resolution = Surface::window_extent(window);
         let swapchain = khr::Swapchain::new(&backend.instance, &backend.device);
-        let swapchain_khr = {
-            let surface_capabilities = unsafe {
-                surface
-                    .get_physical_device_surface_capabilities(
-                        backend.physical_device, surface_khr
-                    ).unwrap()
-            };
-            let desired_image_count = std::cmp::max(1u32, surface_capabilities.min_image_count);
-            let pre_transform = surface_capabilities.current_transform;
-            let present_mode = {
-                let present_modes = unsafe {
-                    surface
-                        .get_physical_device_surface_present_modes(
-                            backend.physical_device, surface_khr
-                        ).unwrap()
-                };
-                present_modes
-                    .iter()
-                    .cloned()
-                    .find(|&mode| mode == vk::PresentModeKHR::MAILBOX)
-                    .unwrap_or(vk::PresentModeKHR::FIFO)
-            };
-            let swapchain_ci = vk::SwapchainCreateInfoKHR {
-                surface: surface_khr,
-                min_image_count: desired_image_count,
-                image_color_space: surface_format.color_space,
-                image_format: surface_format.format,
-                image_extent: surface_capabilities.min_image_extent,
-                image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
-                image_sharing_mode: vk::SharingMode::EXCLUSIVE,
-                image_array_layers: 1,
-                pre_transform,
-                composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
-                present_mode,
-                clipped: true.into(),
-                ..Default::default()
-            };
-
-            unsafe {
-                swapchain
-                    .create_swapchain(&swapchain_ci, None)
-                    .unwrap()
-            }
-        };
-        let present_images = unsafe {
-            swapchain.get_swapchain_images(swapchain_khr).unwrap()
-        };
-        let present_image_views= unsafe {
-            present_images
-                .iter()
-                .map(|&image| {
-                    let image_view_ci = vk::ImageViewCreateInfo {
-                        view_type: vk::ImageViewType::TYPE_2D,
-                        format: surface_format.format,
-                        components: vk::ComponentMapping {
-                            r: vk::ComponentSwizzle::R,
-                            g: vk::ComponentSwizzle::G,
-                            b: vk::ComponentSwizzle::B,
-                            a: vk::ComponentSwizzle::A,
-                        },
-                        subresource_range: vk::ImageSubresourceRange {
-                            aspect_mask: vk::ImageAspectFlags::COLOR,
-                            base_mip_level: 0,
-                            level_count: 1,
-                            base_array_layer: 0,
-                            layer_count: 1
-                        },
-                        image: image,
-                        ..Default::default()
-                    };
-                    backend.device.create_image_view(&image_view_ci, None).unwrap()
-                })
-                .collect::<Vec<vk::ImageView>>()
-        };
-
         let surface_pso_obj = {
             let vert_input_binding_desc = {
                 vec![
@@ -160,13 +175,16 @@ impl Surface {
             };
 
             let pso_desc = PipelineStateObjectDescriptor {
+                name: "surface_pso".to_string(),
                 vs_desc: ShaderProgramDescriptor {
                     path: "./shader/full_screen/full_screen.vert".to_string(),
                     entry: CString::new("main").unwrap(),
+                    ..Default::default()
                 },
                 ps_desc: ShaderProgramDescriptor {
                     path: "./shader/full_screen/full_screen.frag".to_string(),
                     entry: CString::new("main").unwrap(),
+                    ..Default::default()
                 },
                 attachment_desc: render_attachment, // move
                 viewports: vec![vk::Viewport {
@@ -186,10 +204,173 @@ impl Surface {
                 }],
                 input_binding_desc: vert_input_binding_desc,
                 input_attr_desc: vert_input_attr_desc,
+                // full_screen.frag：离屏结果和采样器
+                descriptor_set_layouts: vec![vec![
+                    vk::DescriptorSetLayoutBinding {
+                        binding: 0,
+                        descriptor_type: vk::DescriptorType::SAMPLED_IMAGE,
+                        descriptor_count: 1,
+                        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                        ..Default::default()
+                    },
+                    vk::DescriptorSetLayoutBinding {
+                        binding: 1,
+                        descriptor_type: vk::DescriptorType::SAMPLER,
+                        descriptor_count: 1,
+                        stage_flags: vk::ShaderStageFlags::FRAGMENT,
+                        ..Default::default()
+                    },
+                ]],
+                ..Default::default()
             };
 
-            utility::create_pipeline_state_object(&backend, &pso_desc)
-                .expect("create surface pso obj failed")
+            utility::create_pipeline_state_object(&backend, &pso_desc)?
+        };
+        let mut surface = Surface {
+            surface_format,
+            surface_resolution,
+            present_mode: vk::PresentModeKHR::FIFO,
+            config: config.clone(),
+            swapchain,
+            swapchain_khr: vk::SwapchainKHR::null(),
+            present_images: vec![],
+            present_image_views: vec![],
+            surface_pso_obj,
+            surface_frame_buffers: vec![],
+            backend,
+        };
+        surface.create_swapchain(surface_resolution, vk::SwapchainKHR::null())?;
+        Ok(surface)
+    }
+
+    pub fn info(&self) -> SwapchainInfo
+    {
+        SwapchainInfo {
+            surface_format: self.surface_format,
+            present_mode: self.present_mode,
+            image_count: self.present_images.len() as u32,
+            extent: self.surface_resolution,
+        }
+    }
+
+    // 窗口客户区的物理像素尺寸，最小化时为0
+    pub fn window_extent(window: &winit::Window) -> vk::Extent2D
+    {
+        match window.get_inner_size() {
+            Some(size) => {
+                let size = size.to_physical(window.get_hidpi_factor());
+                vk::Extent2D {
+                    width: size.width.round() as u32,
+                    height: size.height.round() as u32,
+                }
+            },
+            None => vk::Extent2D { width: 0, height: 0 },
+        }
+    }
+
+    // 按新的尺寸重建swapchain、image view和framebuffer，旧swapchain传给驱动复用资源
+    pub fn recreate(&mut self, extent: vk::Extent2D) -> Result<()>
+    {
+        unsafe {
+            self.backend.device.device_wait_idle()?;
+        }
+        self.destroy_frame_buffers();
+        // 重建失败时swapchain_khr保持为null，避免Drop时重复销毁
+        let old_swapchain_khr = std::mem::replace(&mut self.swapchain_khr, vk::SwapchainKHR::null());
+        let ret = self.create_swapchain(extent, old_swapchain_khr);
+        unsafe {
+            self.swapchain.destroy_swapchain(old_swapchain_khr, None);
+        }
+        ret
+    }
+
+    fn create_swapchain(&mut self, desired_extent: vk::Extent2D, old_swapchain_khr: vk::SwapchainKHR)
+        -> Result<()>
+    {
+        let backend = &self.backend;
+        let surface = &backend.surface;
+        let surface_khr = backend.surface_khr;
+        let surface_format = self.surface_format;
+        let surface_capabilities = unsafe {
+            surface
+                .get_physical_device_surface_capabilities(
+                    backend.physical_device, surface_khr
+                )?
+        };
+        // current_extent为0xFFFFFFFF时由swapchain决定尺寸，否则必须与surface一致
+        let surface_resolution = match surface_capabilities.current_extent.width {
+            std::u32::MAX => vk::Extent2D {
+                width: desired_extent.width.max(surface_capabilities.min_image_extent.width)
+                    .min(surface_capabilities.max_image_extent.width),
+                height: desired_extent.height.max(surface_capabilities.min_image_extent.height)
+                    .min(surface_capabilities.max_image_extent.height),
+            },
+            _ => surface_capabilities.current_extent,
+        };
+        let swapchain_khr = {
+            let desired_image_count = choose_image_count(&surface_capabilities, self.config.image_count);
+            let pre_transform = surface_capabilities.current_transform;
+            let present_mode = {
+                let present_modes = unsafe {
+                    surface
+                        .get_physical_device_surface_present_modes(
+                            backend.physical_device, surface_khr
+                        )?
+                };
+                choose_present_mode(&present_modes, &self.config.present_modes)
+            };
+            self.present_mode = present_mode;
+            let swapchain_ci = vk::SwapchainCreateInfoKHR {
+                surface: surface_khr,
+                min_image_count: desired_image_count,
+                image_color_space: surface_format.color_space,
+                image_format: surface_format.format,
+                image_extent: surface_resolution,
+                image_usage: vk::ImageUsageFlags::COLOR_ATTACHMENT,
+                image_sharing_mode: vk::SharingMode::EXCLUSIVE,
+                image_array_layers: 1,
+                pre_transform,
+                composite_alpha: vk::CompositeAlphaFlagsKHR::OPAQUE,
+                present_mode,
+                clipped: true.into(),
+                old_swapchain: old_swapchain_khr,
+                ..Default::default()
+            };
+
+            unsafe {
+                self.swapchain
+                    .create_swapchain(&swapchain_ci, None)?
+            }
+        };
+        let present_images = unsafe {
+            self.swapchain.get_swapchain_images(swapchain_khr)?
+        };
+        let present_image_views= unsafe {
+            present_images
+                .iter()
+                .map(|&image| {
+                    let image_view_ci = vk::ImageViewCreateInfo {
+                        view_type: vk::ImageViewType::TYPE_2D,
+                        format: surface_format.format,
+                        components: vk::ComponentMapping {
+                            r: vk::ComponentSwizzle::R,
+                            g: vk::ComponentSwizzle::G,
+                            b: vk::ComponentSwizzle::B,
+                            a: vk::ComponentSwizzle::A,
+                        },
+                        subresource_range: vk::ImageSubresourceRange {
+                            aspect_mask: vk::ImageAspectFlags::COLOR,
+                            base_mip_level: 0,
+                            level_count: 1,
+                            base_array_layer: 0,
+                            layer_count: 1
+                        },
+                        image,
+                        ..Default::default()
+                    };
+                    backend.device.create_image_view(&image_view_ci, None)
+                })
+                .collect::<std::result::Result<Vec<vk::ImageView>, vk::Result>>()?
         };
         let surface_frame_buffers = {
             present_image_views
@@ -197,7 +378,7 @@ impl Surface {
                 .map(|&present_image_view| {
                     let framebuffer_attachments = [present_image_view];
                     let framebuffer_create_info = vk::FramebufferCreateInfo::builder()
-                        .render_pass(surface_pso_obj.render_pass)
+                        .render_pass(self.surface_pso_obj.render_pass)
                         .attachments(&framebuffer_attachments)
                         .width(surface_resolution.width)
                         .height(surface_resolution.height)
@@ -207,36 +388,102 @@ impl Surface {
                         backend.device
                             .create_framebuffer(
                                 &framebuffer_create_info, None)
-                            .unwrap()
                     }
 
                 })
-                .collect::<Vec<vk::Framebuffer>>()
+                .collect::<std::result::Result<Vec<vk::Framebuffer>, vk::Result>>()?
         };
 
-        Surface {
-            surface_format,
-            surface_resolution,
-            swapchain,
-            swapchain_khr,
-            present_images,
-            present_image_views,
-            surface_pso_obj,
-            surface_frame_buffers,
-            backend,
+        backend.set_object_name(swapchain_khr, "swapchain");
+        for (i, ((&image, &view), &frame_buffer)) in present_images.iter()
+            .zip(present_image_views.iter())
+            .zip(surface_frame_buffers.iter())
+            .enumerate() {
+            backend.set_object_name(image, &format!("swapchain_image_{}", i));
+            backend.set_object_name(view, &format!("swapchain_image_view_{}", i));
+            backend.set_object_name(frame_buffer, &format!("surface_frame_buffer_{}", i));
         }
+
+        self.surface_resolution = surface_resolution;
+        self.swapchain_khr = swapchain_khr;
+        self.present_images = present_images;
+        self.present_image_views = present_image_views;
+        self.surface_frame_buffers = surface_frame_buffers;
+        self.surface_pso_obj.pso_desc.set_viewport_extent(surface_resolution);
+        log::debug!("swapchain created: {:?}", self.info());
+        Ok(())
     }
-}
 
-impl Drop for Surface {
-    fn drop(&mut self)
+    fn destroy_frame_buffers(&mut self)
     {
         unsafe {
+            for &frame_buffer in self.surface_frame_buffers.iter()
+            {
+                self.backend.device.destroy_framebuffer(frame_buffer, None);
+            }
             for &image_view in self.present_image_views.iter()
             {
                 self.backend.device.destroy_image_view(image_view, None);
             }
+        }
+        self.surface_frame_buffers.clear();
+        self.present_image_views.clear();
+        self.present_images.clear();
+    }
+}
+
+impl Drop for Surface {
+    fn drop(&mut self)
+    {
+        self.destroy_frame_buffers();
+        unsafe {
             self.swapchain.destroy_swapchain(self.swapchain_khr, None);
         }
     }
 }
+
+
+#[test]
+fn test_choose_swapchain_config()
+{
+    let supported_modes = [vk::PresentModeKHR::FIFO, vk::PresentModeKHR::IMMEDIATE];
+    assert_eq!(choose_present_mode(&supported_modes, &[vk::PresentModeKHR::MAILBOX, vk::PresentModeKHR::IMMEDIATE]),
+               vk::PresentModeKHR::IMMEDIATE);
+    assert_eq!(choose_present_mode(&supported_modes, &[vk::PresentModeKHR::MAILBOX]),
+               vk::PresentModeKHR::FIFO);
+
+    let supported_formats = [
+        vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_UNORM,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        },
+        vk::SurfaceFormatKHR {
+            format: vk::Format::B8G8R8A8_SRGB,
+            color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+        },
+    ];
+    let chosen = choose_surface_format(&supported_formats, &SwapchainConfig::srgb_formats());
+    assert_eq!(chosen.format, vk::Format::B8G8R8A8_SRGB);
+    let chosen = choose_surface_format(&supported_formats[..1], &SwapchainConfig::srgb_formats());
+    assert_eq!(chosen.format, vk::Format::B8G8R8A8_UNORM);
+    let undefined = [vk::SurfaceFormatKHR {
+        format: vk::Format::UNDEFINED,
+        color_space: vk::ColorSpaceKHR::SRGB_NONLINEAR,
+    }];
+    let chosen = choose_surface_format(&undefined, &SwapchainConfig::srgb_formats());
+    assert_eq!(chosen.format, vk::Format::B8G8R8A8_SRGB);
+
+    let capabilities = vk::SurfaceCapabilitiesKHR {
+        min_image_count: 2,
+        max_image_count: 0,
+        ..Default::default()
+    };
+    assert_eq!(choose_image_count(&capabilities, 1), 2);
+    assert_eq!(choose_image_count(&capabilities, 8), 8);
+    let capabilities = vk::SurfaceCapabilitiesKHR {
+        min_image_count: 2,
+        max_image_count: 3,
+        ..Default::default()
+    };
+    assert_eq!(choose_image_count(&capabilities, 8), 3);
+}
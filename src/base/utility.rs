@@ -5,7 +5,8 @@ use super::pso;
 use super::loader;
 use std::rc;
 use std::boxed;
-use std::io;
+use super::reflect::{self, ShaderReflection};
+use super::error::{Error, Result};
 
 pub fn find_memorytype_index(
     memory_req: &vk::MemoryRequirements,
@@ -55,14 +56,57 @@ macro_rules! offset_of {
 
 
 pub fn create_pipeline_state_object(backend: &rc::Rc<ri::Backend>, desc: &pso::PipelineStateObjectDescriptor)
-    -> io::Result<boxed::Box<pso::PipelineStateObject>>
+    -> Result<boxed::Box<pso::PipelineStateObject>>
 {
-    let vs_mod = loader::load_shader(&backend.device, &desc.vs_desc.path)
-        .expect("vs shader create failed");
-    let ps_mod = loader::load_shader(&backend.device, &desc.ps_desc.path)
-        .expect("ps shader create failed");
+    let (vs_mod, vs_reflection) = loader::load_shader(&backend.device, &desc.vs_desc.path, &desc.vs_desc.defines)?;
+    let (ps_mod, ps_reflection) = match loader::load_shader(&backend.device, &desc.ps_desc.path,
+                                                            &desc.ps_desc.defines) {
+        Ok(shader) => shader,
+        Err(e) => {
+            unsafe {
+                backend.device.destroy_shader_module(vs_mod, None);
+            }
+            return Err(e);
+        },
+    };
+    // 描述中没有给出的顶点输入和descriptor layout由反射生成，给出的与着色器对比
+    let desc = match resolve_layout(desc, &vs_reflection, &ps_reflection) {
+        Ok(desc) => desc,
+        Err(message) => {
+            unsafe {
+                backend.device.destroy_shader_module(vs_mod, None);
+                backend.device.destroy_shader_module(ps_mod, None);
+            }
+            return Err(Error::PipelineLayoutMismatch {
+                name: desc.name.clone(),
+                message,
+            });
+        },
+    };
 
-    let render_pass;{
+    // 之后任何一步失败时，pso_obj被drop，销毁已经创建的对象，还没创建的为null，销毁时忽略
+    let mut pso_obj = Box::new(pso::PipelineStateObject {
+        pso_desc: desc,
+        vs_mod,
+        ps_mod,
+        vs_reflection,
+        ps_reflection,
+        render_pass: vk::RenderPass::null(),
+        pipeline_layout: vk::PipelineLayout::null(),
+        descriptor_set_layouts: vec![],
+        pipeline: vk::Pipeline::null(),
+        device: backend.device.clone(),
+    });
+    let pso::PipelineStateObject {
+        pso_desc: desc,
+        render_pass,
+        pipeline_layout,
+        descriptor_set_layouts,
+        pipeline,
+        ..
+    } = &mut *pso_obj;
+
+    {
     let color_attachment_refs = [vk::AttachmentReference {
         attachment: 0,
         layout: vk::ImageLayout::COLOR_ATTACHMENT_OPTIMAL,
@@ -73,11 +117,14 @@ pub fn create_pipeline_state_object(backend: &rc::Rc<ri::Backend>, desc: &pso::P
         layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
     };
 
-    let subpass1 = vk::SubpassDescription::builder()
+    let mut subpass1 = vk::SubpassDescription::builder()
         .color_attachments(&color_attachment_refs)
-        .depth_stencil_attachment(&depth_attachment_refs)
-        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS)
-        .build();
+        .pipeline_bind_point(vk::PipelineBindPoint::GRAPHICS);
+    // attachment_desc[1]（可选）为depth，没有时subpass不能引用它
+    if desc.attachment_desc.len() > 1 {
+        subpass1 = subpass1.depth_stencil_attachment(&depth_attachment_refs);
+    }
+    let subpass1 = subpass1.build();
 
     let subpasses = [subpass1,];
 
@@ -88,6 +135,16 @@ pub fn create_pipeline_state_object(backend: &rc::Rc<ri::Backend>, desc: &pso::P
         dst_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_READ
             | vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
         ..Default::default()
+    },
+    // 离屏结果在后续pass中作为纹理采样
+    vk::SubpassDependency{
+        src_subpass: 0,
+        dst_subpass: vk::SUBPASS_EXTERNAL,
+        src_stage_mask: vk::PipelineStageFlags::COLOR_ATTACHMENT_OUTPUT,
+        src_access_mask: vk::AccessFlags::COLOR_ATTACHMENT_WRITE,
+        dst_stage_mask: vk::PipelineStageFlags::FRAGMENT_SHADER,
+        dst_access_mask: vk::AccessFlags::SHADER_READ,
+        ..Default::default()
     },];
 
     let render_pass_create_info = vk::RenderPassCreateInfo::builder()
@@ -96,13 +153,99 @@ pub fn create_pipeline_state_object(backend: &rc::Rc<ri::Backend>, desc: &pso::P
         .dependencies(&dependencies);
 
     unsafe {
-        render_pass = backend.device
+        *render_pass = backend.device
             .create_render_pass(
-                &render_pass_create_info,None)
-            .unwrap();
+                &render_pass_create_info,None)?;
+    }
+}
+
+    for bindings in desc.descriptor_set_layouts.iter() {
+        let set_layout = unsafe {
+            let set_layout_ci = vk::DescriptorSetLayoutCreateInfo::builder()
+                .bindings(bindings);
+            backend.device.create_descriptor_set_layout(&set_layout_ci, None)?
+        };
+        descriptor_set_layouts.push(set_layout);
+    }
+
+    unsafe {
+        let layout_create_info = vk::PipelineLayoutCreateInfo::builder()
+            .set_layouts(descriptor_set_layouts)
+            .push_constant_ranges(&desc.push_constant_ranges);
+        *pipeline_layout = backend.device
+            .create_pipeline_layout(&layout_create_info, None)?;
+    }
+
+    *pipeline = create_pipeline(backend, desc, vs_mod, ps_mod, *render_pass, *pipeline_layout)?;
+
+    backend.set_object_name(*pipeline, &desc.name);
+    backend.set_object_name(*pipeline_layout, &format!("{}_layout", desc.name));
+    backend.set_object_name(*render_pass, &format!("{}_render_pass", desc.name));
+    for (set, &set_layout) in descriptor_set_layouts.iter().enumerate() {
+        backend.set_object_name(set_layout, &format!("{}_set_layout_{}", desc.name, set));
+    }
+    backend.set_object_name(vs_mod, &desc.vs_desc.path);
+    backend.set_object_name(ps_mod, &desc.ps_desc.path);
+
+    Ok(pso_obj)
+}
+
+// 重新编译着色器并替换pipeline，render pass、pipeline layout和set layout保持不变，
+// 新着色器必须与它们一致；失败时保留原来的pipeline。调用前device需要空闲
+pub fn reload_pipeline_state_object(backend: &rc::Rc<ri::Backend>, pso_obj: &mut pso::PipelineStateObject)
+    -> Result<()>
+{
+    let (vs_desc, ps_desc) = (&pso_obj.pso_desc.vs_desc, &pso_obj.pso_desc.ps_desc);
+    let (vs_mod, vs_reflection) = loader::load_shader(&backend.device, &vs_desc.path, &vs_desc.defines)?;
+    let (ps_mod, ps_reflection) = match loader::load_shader(&backend.device, &ps_desc.path, &ps_desc.defines) {
+        Ok(shader) => shader,
+        Err(e) => {
+            unsafe {
+                backend.device.destroy_shader_module(vs_mod, None);
+            }
+            return Err(e);
+        },
+    };
+    let pipeline = resolve_layout(&pso_obj.pso_desc, &vs_reflection, &ps_reflection)
+        .map_err(|message| Error::PipelineLayoutMismatch {
+            name: pso_obj.pso_desc.name.clone(),
+            message,
+        })
+        .and_then(|_| create_pipeline(backend, &pso_obj.pso_desc, vs_mod, ps_mod,
+                                      pso_obj.render_pass, pso_obj.pipeline_layout));
+    let pipeline = match pipeline {
+        Ok(pipeline) => pipeline,
+        Err(e) => {
+            unsafe {
+                backend.device.destroy_shader_module(vs_mod, None);
+                backend.device.destroy_shader_module(ps_mod, None);
+            }
+            return Err(e);
+        },
+    };
+
+    backend.set_object_name(pipeline, &pso_obj.pso_desc.name);
+    backend.set_object_name(vs_mod, &pso_obj.pso_desc.vs_desc.path);
+    backend.set_object_name(ps_mod, &pso_obj.pso_desc.ps_desc.path);
+    unsafe {
+        backend.device.destroy_pipeline(pso_obj.pipeline, None);
+        backend.device.destroy_shader_module(pso_obj.vs_mod, None);
+        backend.device.destroy_shader_module(pso_obj.ps_mod, None);
     }
+    pso_obj.pipeline = pipeline;
+    pso_obj.vs_mod = vs_mod;
+    pso_obj.ps_mod = ps_mod;
+    pso_obj.vs_reflection = vs_reflection;
+    pso_obj.ps_reflection = ps_reflection;
+    Ok(())
 }
 
+// render pass和pipeline layout由调用者创建，重新加载着色器时沿用原来的
+fn create_pipeline(backend: &ri::Backend, desc: &pso::PipelineStateObjectDescriptor,
+                   vs_mod: vk::ShaderModule, ps_mod: vk::ShaderModule,
+                   render_pass: vk::RenderPass, pipeline_layout: vk::PipelineLayout)
+    -> Result<vk::Pipeline>
+{
     let stage_ci = vec![
         vk::PipelineShaderStageCreateInfo {
             module: vs_mod,
@@ -156,7 +299,7 @@ pub fn create_pipeline_state_object(backend: &rc::Rc<ri::Backend>, desc: &pso::P
     let depth_stencil_state_ci = vk::PipelineDepthStencilStateCreateInfo {
         depth_test_enable: 1,
         depth_write_enable: 1,
-        depth_compare_op: vk::CompareOp::LESS_OR_EQUAL,
+        depth_compare_op: desc.depth_compare_op,
         front: stencil_op_state,
         back: stencil_op_state,
         max_depth_bounds: 1.0,
@@ -175,14 +318,6 @@ pub fn create_pipeline_state_object(backend: &rc::Rc<ri::Backend>, desc: &pso::P
 
     let dynamic_state = vec![vk::DynamicState::VIEWPORT, vk::DynamicState::SCISSOR];
 
-    let pipeline_layout;
-    unsafe {
-        let layout_create_info = vk::PipelineLayoutCreateInfo::default();
-        pipeline_layout = backend.device
-            .create_pipeline_layout(&layout_create_info, None)
-            .unwrap();
-    }
-
     let pipeline_ci = vk::GraphicsPipelineCreateInfo::builder()
         .stages(&stage_ci)
         .vertex_input_state(&vert_input_state_ci)
@@ -202,23 +337,86 @@ pub fn create_pipeline_state_object(backend: &rc::Rc<ri::Backend>, desc: &pso::P
         .render_pass(render_pass)
         .build();
 
-    let pipeline;
-    unsafe {
-        pipeline = backend.device
+    let pipeline = unsafe {
+        backend.device
             .create_graphics_pipelines(
                 vk::PipelineCache::null(),
                 &[pipeline_ci],
                 None,
-            ).expect("unable to create graphic pipeline");
+            ).map_err(|(_, err_code)| err_code)?
+    };
+    Ok(pipeline[0])
+}
+
+fn resolve_layout(desc: &pso::PipelineStateObjectDescriptor, vs_reflection: &ShaderReflection,
+                  ps_reflection: &ShaderReflection)
+    -> std::result::Result<pso::PipelineStateObjectDescriptor, String>
+{
+    let mut desc = desc.clone();
+    if desc.input_binding_desc.is_empty() && desc.input_attr_desc.is_empty() {
+        let (input_binding_desc, input_attr_desc) = reflect::vertex_input_desc(vs_reflection);
+        desc.input_binding_desc = input_binding_desc;
+        desc.input_attr_desc = input_attr_desc;
+    } else {
+        reflect::validate_vertex_input(vs_reflection, &desc.input_attr_desc)?;
+    }
+    if desc.descriptor_set_layouts.is_empty() {
+        desc.descriptor_set_layouts = reflect::merge_descriptor_bindings(&[vs_reflection, ps_reflection])?;
+    } else {
+        reflect::validate_descriptor_bindings(vs_reflection, &desc.descriptor_set_layouts)?;
+        reflect::validate_descriptor_bindings(ps_reflection, &desc.descriptor_set_layouts)?;
     }
+    if desc.push_constant_ranges.is_empty() {
+        desc.push_constant_ranges = [vs_reflection, ps_reflection]
+            .iter()
+            .filter_map(|reflection| reflection.push_constant_range)
+            .collect();
+    } else {
+        reflect::validate_push_constants(vs_reflection, &desc.push_constant_ranges)?;
+        reflect::validate_push_constants(ps_reflection, &desc.push_constant_ranges)?;
+    }
+    Ok(desc)
+}
 
-    Ok(Box::new(pso::PipelineStateObject{
-        pso_desc: desc.clone(),
-        vs_mod,
-        ps_mod,
-        render_pass,
-        pipeline_layout,
-        pipeline: pipeline[0],
-        device: backend.device.clone(),
-    }))
+// 录制并提交一次性命令，等待执行完成后返回
+pub fn submit_one_time_commands<F: FnOnce(&ash::Device, vk::CommandBuffer)>(
+    device: &ash::Device,
+    cmd_pool: vk::CommandPool,
+    queue: vk::Queue,
+    f: F,
+) -> Result<()> {
+    let cmd_buf = unsafe {
+        let ci = vk::CommandBufferAllocateInfo {
+            command_buffer_count: 1,
+            command_pool: cmd_pool,
+            level: vk::CommandBufferLevel::PRIMARY,
+            ..Default::default()
+        };
+        device.allocate_command_buffers(&ci)?[0]
+    };
+    let fence = unsafe {
+        device.create_fence(&vk::FenceCreateInfo::default(), None)?
+    };
+    let cmd_bufs = [cmd_buf];
+    let ret = unsafe {
+        let begin_info = vk::CommandBufferBeginInfo::builder()
+            .flags(vk::CommandBufferUsageFlags::ONE_TIME_SUBMIT);
+        device.begin_command_buffer(cmd_buf, &begin_info)
+            .and_then(|_| {
+                f(device, cmd_buf);
+                device.end_command_buffer(cmd_buf)
+            })
+            .and_then(|_| {
+                let submit_info = vk::SubmitInfo::builder()
+                    .command_buffers(&cmd_bufs)
+                    .build();
+                device.queue_submit(queue, &[submit_info], fence)
+            })
+            .and_then(|_| device.wait_for_fences(&[fence], true, u64::MAX))
+    };
+    unsafe {
+        device.destroy_fence(fence, None);
+        device.free_command_buffers(cmd_pool, &cmd_bufs);
+    }
+    Ok(ret?)
 }
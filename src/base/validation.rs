@@ -0,0 +1,130 @@
+use ash::vk;
+use std::ffi::CStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// ash 0.31中还没有这个枚举值（VK_VALIDATION_FEATURE_ENABLE_SYNCHRONIZATION_VALIDATION_EXT）
+const SYNCHRONIZATION_VALIDATION: vk::ValidationFeatureEnableEXT =
+    vk::ValidationFeatureEnableEXT::from_raw(4);
+
+#[derive(Clone, Debug)]
+pub struct ValidationConfig {
+    // 开启验证层和debug messenger，没有安装验证层时自动跳过
+    pub enabled: bool,
+    // 以下两项需要VK_EXT_validation_features，开销较大
+    pub synchronization: bool,
+    pub gpu_assisted: bool,
+    pub message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    pub message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            enabled: true,
+            synchronization: false,
+            gpu_assisted: false,
+            message_severity: vk::DebugUtilsMessageSeverityFlagsEXT::ERROR
+                | vk::DebugUtilsMessageSeverityFlagsEXT::WARNING
+                | vk::DebugUtilsMessageSeverityFlagsEXT::INFO,
+            message_type: vk::DebugUtilsMessageTypeFlagsEXT::all(),
+        }
+    }
+}
+
+impl ValidationConfig {
+    pub fn disabled() -> Self {
+        ValidationConfig {
+            enabled: false,
+            ..Default::default()
+        }
+    }
+
+    pub fn needs_validation_features(&self) -> bool {
+        self.enabled && (self.synchronization || self.gpu_assisted)
+    }
+
+    pub fn enabled_validation_features(&self) -> Vec<vk::ValidationFeatureEnableEXT> {
+        let mut features = vec![];
+        if self.synchronization {
+            features.push(SYNCHRONIZATION_VALIDATION);
+        }
+        if self.gpu_assisted {
+            features.push(vk::ValidationFeatureEnableEXT::GPU_ASSISTED);
+        }
+        features
+    }
+}
+
+pub fn log_level(severity: vk::DebugUtilsMessageSeverityFlagsEXT) -> log::Level {
+    if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR) {
+        log::Level::Error
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::WARNING) {
+        log::Level::Warn
+    } else if severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::INFO) {
+        log::Level::Debug
+    } else {
+        log::Level::Trace
+    }
+}
+
+// user_data指向Backend持有的错误计数器
+pub(crate) unsafe extern "system" fn vulkan_debug_callback(
+    message_severity: vk::DebugUtilsMessageSeverityFlagsEXT,
+    message_type: vk::DebugUtilsMessageTypeFlagsEXT,
+    p_callback_data: *const vk::DebugUtilsMessengerCallbackDataEXT,
+    p_user_data: *mut std::os::raw::c_void,
+) -> vk::Bool32 {
+    let callback_data = *p_callback_data;
+    let message_id_number = callback_data.message_id_number;
+    let message_id_name = match callback_data.p_message_id_name.is_null() {
+        true => "".into(),
+        false => CStr::from_ptr(callback_data.p_message_id_name).to_string_lossy(),
+    };
+    let message = CStr::from_ptr(callback_data.p_message).to_string_lossy();
+
+    if message_severity.contains(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR)
+        && !p_user_data.is_null() {
+        let error_count = &*(p_user_data as *const AtomicUsize);
+        error_count.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let level = log_level(message_severity);
+    // 没有设置logger时直接输出，避免验证错误被吞掉
+    if log::max_level() == log::LevelFilter::Off {
+        println!(
+            "{:?}:{:?} [{}:{}] :\n{}\n",
+            message_severity,
+            message_type,
+            message_id_number,
+            message_id_name,
+            message
+        );
+    } else {
+        log::log!(
+            target: "vulkan",
+            level,
+            "{:?} [{}:{}] {}",
+            message_type,
+            message_id_number,
+            message_id_name,
+            message
+        );
+    }
+
+    vk::FALSE
+}
+
+
+#[test]
+fn test_validation_config()
+{
+    assert_eq!(log_level(vk::DebugUtilsMessageSeverityFlagsEXT::ERROR), log::Level::Error);
+    assert_eq!(log_level(vk::DebugUtilsMessageSeverityFlagsEXT::VERBOSE), log::Level::Trace);
+    let config = ValidationConfig {
+        synchronization: true,
+        ..Default::default()
+    };
+    assert!(config.needs_validation_features());
+    assert_eq!(config.enabled_validation_features(), vec![SYNCHRONIZATION_VALIDATION]);
+    assert!(!ValidationConfig::disabled().needs_validation_features());
+}
@@ -1,16 +1,26 @@
-// use ash::vk;
-// use rt_vk_example::*;
+use std::boxed;
+use std::cell::RefCell;
+use rt_vk_example::app;
+use rt_vk_example::samples::cube::CubeRenderLoop;
+use rt_vk_example::app::RenderLoopAction;
 
 fn main()
 {
-    // println!("current dir: {:?}", std::env::current_dir());
-    // let app_ci = app::AppCreateInfo {
-    //     app_name: "triangle".to_string(),
-    //     title: "triangle".to_string(),
-    //     events_loop: winit::EventsLoop::new(),
-    //     width: 1920.0,
-    //     height: 1080.0,
-    // };
-    // let exp_app = app::App::new(&app_ci);
+    println!("current dir: {:?}", std::env::current_dir());
+    let app_ci = app::AppCreateInfo {
+        app_name: "cube".to_string(),
+        title: "cube".to_string(),
+        width: 800.0,
+        height: 600.0,
+        ..Default::default()
+    };
+    let mut app_obj = app::App::new(&app_ci)
+        .expect("create app failed");
+    let cube_rl = CubeRenderLoop::new(&mut app_obj)
+        .expect("create cube render loop failed");
+    app_obj.render_loop_obj = RefCell::new(boxed::Box::new(cube_rl));
 
-}
\ No newline at end of file
+    if let Err(e) = app_obj.render_loop() {
+        eprintln!("render loop failed: {}", e);
+    }
+}
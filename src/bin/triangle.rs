@@ -1,204 +1,9 @@
-use ash::vk;
-use ash::version::*;
-use std::default::Default;
-use std::ffi::CString;
-use std::{mem, boxed};
-use std::cell;
+use std::boxed;
+use std::cell::RefCell;
 use rt_vk_example::app;
-use rt_vk_example::offset_of;
-use rt_vk_example::base::*;
-use rt_vk_example::base::pso::ShaderProgramDescriptor;
-use std::ops;
+use rt_vk_example::samples::triangle::TriangleRenderLoop;
 use rt_vk_example::app::RenderLoopAction;
 
-#[derive(Clone, Debug, Copy)]
-struct Vertex {
-    pos: [f32; 4],
-    color: [f32; 4],
-}
-
-struct TriangleRenderLoop {
-    pub device: ash::Device,
-    pub render_pass: vk::RenderPass,
-    pub frame_buffer: vk::Framebuffer,
-    pub pso_obj: boxed::Box<pso::PipelineStateObject>,
-    pub vb: buffer::BufferSlice<Vertex>,
-    pub ib: buffer::BufferSlice<u16>,
-    pub scr_vb: buffer::BufferSlice<f32>,
-    pub scr_ib: buffer::BufferSlice<u16>,
-}
-
-impl TriangleRenderLoop {
-    fn render_screen(&self, app_obj: &app::App)
-    {
-        let present_idx = app_obj.acquire_next_image() as usize;
-
-        if present_idx >= app_obj.surface.surface_frame_buffers.len() {
-            return;
-        }
-
-        let clear_values = {
-            [
-                vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 0.0],
-                    }
-                },
-            ]
-        };
-
-        let render_pass_begin_info = {
-            vk::RenderPassBeginInfo::builder()
-                .render_pass(app_obj.surface.surface_pso_obj.render_pass)
-                .clear_values(&clear_values)
-                .framebuffer(app_obj.surface.surface_frame_buffers[present_idx])
-                .render_area(vk::Rect2D {
-                    offset: vk::Offset2D{x: 0, y: 0},
-                    extent: app_obj.surface.surface_resolution,
-                })
-                .build()
-        };
-
-        let device = &app_obj.backend.borrow().device;
-        let cmd_buf = app_obj.graphic_cmd_buffer;
-
-        unsafe {
-            device.cmd_begin_render_pass(
-                cmd_buf,
-                &render_pass_begin_info,
-                vk::SubpassContents::INLINE
-            );
-            device.cmd_bind_pipeline(
-                cmd_buf,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pso_obj.pipeline
-            );
-            device.cmd_set_viewport(
-                cmd_buf,
-                0,
-                &self.pso_obj.pso_desc.viewports,
-            );
-            device.cmd_set_scissor(
-                cmd_buf,
-                0,
-                &self.pso_obj.pso_desc.scissors
-            );
-            device.cmd_bind_vertex_buffers(
-                cmd_buf,
-                0,
-                &[app_obj.buf_mgr_sys.vertex_buffer.buffer],
-                &[self.scr_vb.offset],
-            );
-            device.cmd_bind_index_buffer(
-                cmd_buf,
-                app_obj.buf_mgr_sys.index_buffer.buffer,
-                self.scr_ib.offset,
-                vk::IndexType::UINT16
-            );
-            device.cmd_draw_indexed(
-                cmd_buf,
-                self.scr_ib.size as u32,
-                1, 0, 0, 1
-            );
-            device.cmd_end_render_pass(
-                cmd_buf,
-            );
-        }
-    }
-}
-
-impl app::RenderLoop for TriangleRenderLoop {
-    fn render(&self, app_obj: &app::App)
-    {
-        let present_idx = app_obj.acquire_next_image() as usize;
-        if present_idx >= app_obj.surface.surface_frame_buffers.len() {
-            return;
-        }
-        let clear_values = {
-            [
-                vk::ClearValue {
-                    color: vk::ClearColorValue {
-                        float32: [0.0, 0.0, 0.0, 0.0],
-                    }
-                },
-            ]
-        };
-        let render_pass_begin_info = {
-            vk::RenderPassBeginInfo::builder()
-                .render_pass(self.render_pass)
-                .clear_values(&clear_values)
-                .framebuffer(self.frame_buffer)
-                .render_area(vk::Rect2D {
-                    offset: vk::Offset2D{x: 0, y: 0},
-                    extent: app_obj.surface.surface_resolution,
-                })
-                .build()
-        };
-
-        let device = &app_obj.backend.borrow().device;
-        let cmd_buf = app_obj.graphic_cmd_buffer;
-        unsafe {
-            device.cmd_begin_render_pass(
-                cmd_buf,
-                &render_pass_begin_info,
-                vk::SubpassContents::INLINE
-            );
-            device.cmd_bind_pipeline(
-                cmd_buf,
-                vk::PipelineBindPoint::GRAPHICS,
-                self.pso_obj.pipeline
-            );
-            device.cmd_set_viewport(
-                cmd_buf,
-                0,
-                &self.pso_obj.pso_desc.viewports,
-            );
-            device.cmd_set_scissor(
-                cmd_buf,
-                0,
-                &self.pso_obj.pso_desc.scissors
-            );
-            device.cmd_bind_vertex_buffers(
-                cmd_buf,
-                0,
-                &[app_obj.buf_mgr_sys.vertex_buffer.buffer],
-                &[self.vb.offset],
-            );
-            device.cmd_bind_index_buffer(
-                cmd_buf,
-                app_obj.buf_mgr_sys.index_buffer.buffer,
-                self.ib.offset,
-                vk::IndexType::UINT16
-            );
-            device.cmd_draw_indexed(
-                cmd_buf,
-                self.ib.size as u32,
-                1, 0, 0, 1
-            );
-            device.cmd_end_render_pass(
-                cmd_buf,
-            );
-        }
-
-        self.render_screen(app_obj);
-    }
-
-    fn update(&self, app_obj: &app::App, delta_time: f64)
-    {
-
-    }
-
-}
-
-impl Drop for TriangleRenderLoop {
-    fn drop(&mut self)
-    {
-        unsafe {
-            self.device.destroy_framebuffer(self.frame_buffer, None);
-        }
-    }
-}
-
 fn main()
 {
     println!("current dir: {:?}", std::env::current_dir());
@@ -207,225 +12,13 @@ fn main()
         title: "triangle".to_string(),
         width: 800.0,
         height: 600.0,
-    };
-    let mut app_obj = app::App::new(&app_ci);
-
-    // attachment
-    let render_attachment = {
-        vec![
-            vk::AttachmentDescription {
-                format: app_obj.surface.surface_format.format,
-                samples: vk::SampleCountFlags::TYPE_1,
-                load_op: vk::AttachmentLoadOp::CLEAR,
-                store_op: vk::AttachmentStoreOp::STORE,
-                final_layout: vk::ImageLayout::PRESENT_SRC_KHR,
-                ..Default::default()
-            },
-            vk::AttachmentDescription {
-                format: vk::Format::D16_UNORM,
-                samples: vk::SampleCountFlags::TYPE_1,
-                load_op: vk::AttachmentLoadOp::CLEAR,
-                initial_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
-                ..Default::default()
-            },
-        ]
-    };
-    // vert input binding desc
-    let vert_input_binding_desc = {
-        vec![
-            vk::VertexInputBindingDescription {
-                binding: 0,
-                stride: mem::size_of::<Vertex>() as u32,
-                input_rate: vk::VertexInputRate::VERTEX,
-            }
-        ]
-    };
-    // vert input attr desc
-    let vert_input_attr_desc = {
-        vec![
-            vk::VertexInputAttributeDescription {
-                location: 0,
-                binding: 0,
-                format: vk::Format::R32G32_SFLOAT,
-                offset: offset_of!(Vertex, pos) as u32,
-                ..Default::default()
-            },
-            vk::VertexInputAttributeDescription {
-                location: 1,
-                binding: 0,
-                format: vk::Format::R32G32B32A32_SFLOAT,
-                offset: offset_of!(Vertex, color) as u32,
-                ..Default::default()
-            }
-        ]
-    };
-    let pso_desc = pso::PipelineStateObjectDescriptor {
-        vs_desc: ShaderProgramDescriptor {
-            path: "./shader/triangle/triangle.vert".to_string(),
-            entry: CString::new("main").unwrap(),
-        },
-        ps_desc: ShaderProgramDescriptor {
-            path: "./shader/triangle/triangle.frag".to_string(),
-            entry: CString::new("main").unwrap(),
-        },
-        attachment_desc: render_attachment, // move
-        viewports: vec![vk::Viewport {
-            x: 0.0, y: 0.0,
-            width: app_obj.surface.surface_resolution.width as f32,
-            height: app_obj.surface.surface_resolution.height as f32,
-            min_depth: 0.0,
-            max_depth: 1.0,
-        }],
-        scissors: vec![vk::Rect2D {
-            offset: vk::Offset2D { x: 0, y: 0 },
-            extent: vk::Extent2D {
-                width: app_obj.surface.surface_resolution.width,
-                height: app_obj.surface.surface_resolution.height
-            }
-        }],
-        input_binding_desc: vert_input_binding_desc,
-        input_attr_desc: vert_input_attr_desc,
-    };
-    let pso_obj = utility::create_pipeline_state_object(&app_obj.backend.borrow(), &pso_desc)
-        .expect("create pso failed");
-    // create color image
-    let color_image_ci = vk::ImageCreateInfo {
-        image_type: vk::ImageType::TYPE_2D,
-        format: app_obj.surface.surface_format.format,
-        extent: vk::Extent3D {
-            width: app_obj.surface.surface_resolution.width,
-            height: app_obj.surface.surface_resolution.height,
-            depth: 1,
-        },
-        mip_levels: 1,
-        array_layers: 1,
-        samples: vk::SampleCountFlags::TYPE_1,
-        tiling: vk::ImageTiling::OPTIMAL,
-        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
-        sharing_mode: vk::SharingMode::EXCLUSIVE,
-        ..Default::default()
-    };
-    let depth_image_ci = vk::ImageCreateInfo {
-        image_type: vk::ImageType::TYPE_2D,
-        format: vk::Format::D16_UNORM,
-        extent: vk::Extent3D {
-            width: app_obj.surface.surface_resolution.width,
-            height: app_obj.surface.surface_resolution.height,
-            depth: 1,
-        },
-        mip_levels: 1,
-        array_layers: 1,
-        samples: vk::SampleCountFlags::TYPE_1,
-        tiling: vk::ImageTiling::OPTIMAL,
-        usage: vk::ImageUsageFlags::COLOR_ATTACHMENT | vk::ImageUsageFlags::SAMPLED,
-        sharing_mode: vk::SharingMode::EXCLUSIVE,
-        ..Default::default()
-    };
-    let color_image = unsafe {
-        app_obj.backend.borrow()
-            .device
-            .create_image(&color_image_ci, None)
-            .unwrap()
-    };
-    let depth_image = unsafe {
-        app_obj.backend.borrow()
-            .device
-            .create_image(&depth_image_ci, None)
-            .unwrap()
-    };
-    let color_image_view_ci = vk::ImageViewCreateInfo {
-        view_type: vk::ImageViewType::TYPE_2D,
-        image: color_image,
-        format: color_image_ci.format,
         ..Default::default()
     };
-    let depth_image_view_ci = vk::ImageViewCreateInfo {
-        view_type: vk::ImageViewType::TYPE_2D,
-        image: depth_image,
-        format: depth_image_ci.format,
-        ..Default::default()
-    };
-    let color_view = unsafe {
-        app_obj.backend.borrow().device
-            .create_image_view(&color_image_view_ci, None)
-            .unwrap()
-    };
-    let depth_view = unsafe {
-        app_obj.backend.borrow().device
-            .create_image_view(&depth_image_view_ci, None)
-            .unwrap()
-    };
-
-    let frame_buffer = {
-        let framebuffer_attachments = [color_view, depth_view];
-        let fb_ci = vk::FramebufferCreateInfo::builder()
-            .render_pass(pso_obj.render_pass)
-            .attachments(&framebuffer_attachments)
-            .width(app_obj.surface.surface_resolution.width)
-            .height(app_obj.surface.surface_resolution.height)
-            .layers(1);
-        unsafe {
-            app_obj.backend.borrow().device
-                .create_framebuffer(
-                    &fb_ci, None
-                ).unwrap()
-        }
-    };
-
-    // vertex buffer
-    let vertices= {
-         vec![
-            Vertex {
-                pos: [-1.0, 1.0, 0.0, 1.0],
-                color: [0.0, 1.0, 0.0, 1.0],
-            },
-            Vertex {
-                pos: [1.0, 1.0, 0.0, 1.0],
-                color: [0.0, 0.0, 1.0, 1.0],
-            },
-            Vertex {
-                pos: [0.0, -1.0, 0.0, 1.0],
-                color: [1.0, 0.0, 0.0, 1.0],
-            },
-        ]
-    };
-    let vb_size = (vertices.len() * std::mem::size_of::<Vertex>()) as u64;
-    let mut vb = app_obj.buf_mgr_sys.allocate_vertex_buffer::<Vertex>(vb_size);
-    vb.slice.copy_from_slice(&vertices);
-    // index buffer
-    let ib_data = [0u16, 1, 2];
-    let ib_size = (ib_data.len() * std::mem::size_of::<u32>()) as u64;
-    let mut ib = app_obj.buf_mgr_sys.allocate_index_buffer(ib_size);
-    ib.slice.copy_from_slice(&ib_data);
-
-    // scr vertex buffer
-    let scr_vertices = [
-        0.0, 0.0,
-        1.0, 0.0,
-        1.1, 1.1,
-        0.0, 1.0,
-    ];
-    let mut scr_vb = app_obj.buf_mgr_sys.allocate_vertex_buffer::<f32>(vb_size);
-    scr_vb.slice.copy_from_slice(&scr_vertices);
-
-    let scr_ib_data = [0u16, 1, 2, 0, 2, 3];
-    let mut scr_ib = app_obj.buf_mgr_sys.allocate_index_buffer(6);
-    scr_ib.slice.copy_from_slice(&scr_ib_data);
-
-    let triangle_rl = {
-        TriangleRenderLoop {
-            device: app_obj.backend.borrow().device.clone(),
-            render_pass: pso_obj.render_pass,
-            frame_buffer,
-            pso_obj,
-            vb,
-            ib,
-            scr_vb,
-            scr_ib,
-        }
-    };
-    app_obj.render_loop_obj = boxed::Box::new(triangle_rl);
+    let mut app_obj = app::App::new(&app_ci)
+        .expect("create app failed");
+    let triangle_rl = TriangleRenderLoop::new(&mut app_obj)
+        .expect("create triangle render loop failed");
+    app_obj.render_loop_obj = RefCell::new(boxed::Box::new(triangle_rl));
 
     {
         // // render loop
@@ -533,6 +126,8 @@ fn main()
         // });
     }
 
-    app_obj.render_loop();
+    if let Err(e) = app_obj.render_loop() {
+        eprintln!("render loop failed: {}", e);
+    }
 }
 
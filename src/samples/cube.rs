@@ -0,0 +1,374 @@
+use ash::vk;
+use ash::version::*;
+use directx_math::*;
+use std::cell::RefCell;
+use std::default::Default;
+use std::ffi::CString;
+use std::{mem, boxed};
+use crate::app;
+use crate::offset_of;
+use crate::base::*;
+use crate::base::utility;
+use crate::base::descriptor::DescriptorSetWriter;
+use crate::base::camera::{Camera, OrbitController};
+use crate::base::scene::Transform;
+use crate::base::pso::ShaderProgramDescriptor;
+use crate::base::error::Result;
+use super::screen::ScreenQuad;
+
+#[derive(Clone, Debug, Copy)]
+pub struct Vertex {
+    pub pos: [f32; 3],
+    pub color: [f32; 3],
+}
+
+#[repr(C)]
+#[derive(Clone, Debug, Copy, Default)]
+pub struct CubeUniform {
+    pub mvp: XMFLOAT4X4,
+}
+
+pub struct CubeRenderLoop {
+    pub device: ash::Device,
+    pub render_pass: vk::RenderPass,
+    pub render_target: render_target::RenderTarget,
+    pub pso_obj: boxed::Box<pso::PipelineStateObject>,
+    // 每个frame in flight一份uniform和descriptor set，不改写GPU可能还在读取的数据
+    pub descriptor_sets: Vec<vk::DescriptorSet>,
+    pub uniforms: RefCell<Vec<buffer::BufferSlice<CubeUniform>>>,
+    pub vb: buffer::BufferSlice<Vertex>,
+    pub ib: buffer::BufferSlice<u16>,
+    pub ib_count: u32,
+    pub screen: ScreenQuad,
+    pub camera: RefCell<Camera>,
+    pub orbit: RefCell<OrbitController>,
+    pub model: RefCell<Transform>,
+    // 绕Y轴，弧度/秒
+    pub rotation_speed: f32,
+}
+
+impl CubeRenderLoop {
+    pub fn new(app_obj: &mut app::App) -> Result<Self>
+    {
+        let backend = app_obj.backend.borrow().clone();
+        let device = &backend.device;
+        let resolution = app_obj.resolution();
+        let frame_count = app_obj.frames.len();
+
+        // attachment
+        let render_attachment = {
+            vec![
+                vk::AttachmentDescription {
+                    format: vk::Format::R8G8B8A8_UNORM,
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    load_op: vk::AttachmentLoadOp::CLEAR,
+                    store_op: vk::AttachmentStoreOp::STORE,
+                    final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ..Default::default()
+                },
+                vk::AttachmentDescription {
+                    format: vk::Format::D16_UNORM,
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    load_op: vk::AttachmentLoadOp::CLEAR,
+                    initial_layout: vk::ImageLayout::UNDEFINED,
+                    final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    ..Default::default()
+                },
+            ]
+        };
+        // vert input binding desc
+        let vert_input_binding_desc = {
+            vec![
+                vk::VertexInputBindingDescription {
+                    binding: 0,
+                    stride: mem::size_of::<Vertex>() as u32,
+                    input_rate: vk::VertexInputRate::VERTEX,
+                }
+            ]
+        };
+        // vert input attr desc
+        let vert_input_attr_desc = {
+            vec![
+                vk::VertexInputAttributeDescription {
+                    location: 0,
+                    binding: 0,
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: offset_of!(Vertex, pos) as u32,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 1,
+                    binding: 0,
+                    format: vk::Format::R32G32B32_SFLOAT,
+                    offset: offset_of!(Vertex, color) as u32,
+                }
+            ]
+        };
+        // camera
+        let mut camera = Camera::perspective(
+            XMConvertToRadians(60.0),
+            resolution.width as f32 / resolution.height as f32,
+            0.1,
+            100.0,
+        );
+        let orbit = OrbitController {
+            distance: 3.0,
+            yaw: XMConvertToRadians(30.0),
+            pitch: XMConvertToRadians(25.0),
+            ..Default::default()
+        };
+        orbit.apply(&mut camera);
+
+        let mut pso_desc = pso::PipelineStateObjectDescriptor {
+            name: "cube_pso".to_string(),
+            vs_desc: ShaderProgramDescriptor {
+                path: "./shader/cube/cube.vert".to_string(),
+                entry: CString::new("main").unwrap(),
+                ..Default::default()
+            },
+            ps_desc: ShaderProgramDescriptor {
+                path: "./shader/cube/cube.frag".to_string(),
+                entry: CString::new("main").unwrap(),
+                ..Default::default()
+            },
+            attachment_desc: render_attachment, // move
+            input_binding_desc: vert_input_binding_desc,
+            input_attr_desc: vert_input_attr_desc,
+            descriptor_set_layouts: vec![vec![
+                vk::DescriptorSetLayoutBinding {
+                    binding: 0,
+                    descriptor_type: vk::DescriptorType::UNIFORM_BUFFER,
+                    descriptor_count: 1,
+                    stage_flags: vk::ShaderStageFlags::VERTEX,
+                    ..Default::default()
+                },
+            ]],
+            depth_compare_op: camera.depth_compare_op(),
+            ..Default::default()
+        };
+        pso_desc.set_viewport_extent(resolution);
+        let pso_obj = utility::create_pipeline_state_object(&backend, &pso_desc)?;
+        let render_target = render_target::RenderTarget::new(
+            &backend,
+            &pso_obj,
+            resolution,
+        )?;
+
+        // uniform buffer
+        let uniforms = (0..frame_count)
+            .map(|_| app_obj.buf_mgr_sys.allocate_uniform_buffer::<CubeUniform>(
+                mem::size_of::<CubeUniform>() as u64))
+            .collect::<Vec<buffer::BufferSlice<CubeUniform>>>();
+        let descriptor_sets = uniforms
+            .iter()
+            .map(|uniform| {
+                let descriptor_set = app_obj.descriptor_allocator
+                    .allocate(pso_obj.descriptor_set_layouts[0])?;
+                DescriptorSetWriter::new(descriptor_set)
+                    .uniform_buffer(0, uniform)
+                    .update(device);
+                Ok(descriptor_set)
+            })
+            .collect::<Result<Vec<vk::DescriptorSet>>>()?;
+
+        // 每个面4个顶点，逆时针（从外侧看），颜色按面区分
+        let faces = [
+            // +X
+            ([[0.5, -0.5, 0.5], [0.5, -0.5, -0.5], [0.5, 0.5, -0.5], [0.5, 0.5, 0.5]],
+             [1.0, 0.2, 0.2]),
+            // -X
+            ([[-0.5, -0.5, -0.5], [-0.5, -0.5, 0.5], [-0.5, 0.5, 0.5], [-0.5, 0.5, -0.5]],
+             [0.2, 1.0, 1.0]),
+            // +Y
+            ([[-0.5, 0.5, 0.5], [0.5, 0.5, 0.5], [0.5, 0.5, -0.5], [-0.5, 0.5, -0.5]],
+             [0.2, 1.0, 0.2]),
+            // -Y
+            ([[-0.5, -0.5, -0.5], [0.5, -0.5, -0.5], [0.5, -0.5, 0.5], [-0.5, -0.5, 0.5]],
+             [1.0, 0.2, 1.0]),
+            // +Z
+            ([[-0.5, -0.5, 0.5], [0.5, -0.5, 0.5], [0.5, 0.5, 0.5], [-0.5, 0.5, 0.5]],
+             [0.2, 0.2, 1.0]),
+            // -Z
+            ([[0.5, -0.5, -0.5], [-0.5, -0.5, -0.5], [-0.5, 0.5, -0.5], [0.5, 0.5, -0.5]],
+             [1.0, 1.0, 0.2]),
+        ];
+        let vertices = faces
+            .iter()
+            .flat_map(|(corners, color)| {
+                corners.iter().map(move |&pos| Vertex { pos, color: *color })
+            })
+            .collect::<Vec<Vertex>>();
+        let indices = (0..faces.len() as u16)
+            .flat_map(|face| {
+                let base = face * 4;
+                vec![base, base + 1, base + 2, base, base + 2, base + 3]
+            })
+            .collect::<Vec<u16>>();
+
+        // vertex buffer
+        let vb_size = (vertices.len() * mem::size_of::<Vertex>()) as u64;
+        let mut vb = app_obj.buf_mgr_sys.allocate_vertex_buffer::<Vertex>(vb_size);
+        vb.slice.copy_from_slice(&vertices);
+        // index buffer
+        let ib_size = (indices.len() * mem::size_of::<u16>()) as u64;
+        let mut ib = app_obj.buf_mgr_sys.allocate_index_buffer(ib_size);
+        ib.slice.copy_from_slice(&indices);
+
+        let screen = ScreenQuad::new(app_obj, render_target.color_view)?;
+
+        Ok(CubeRenderLoop {
+            device: device.clone(),
+            render_pass: pso_obj.render_pass,
+            render_target,
+            pso_obj,
+            descriptor_sets,
+            uniforms: RefCell::new(uniforms),
+            vb,
+            ib,
+            ib_count: indices.len() as u32,
+            screen,
+            camera: RefCell::new(camera),
+            orbit: RefCell::new(orbit),
+            model: RefCell::new(Transform::default()),
+            rotation_speed: XMConvertToRadians(45.0),
+        })
+    }
+
+    // 写入这一帧的MVP
+    fn update_uniform(&self, frame_index: usize)
+    {
+        let mvp = XMMatrixMultiply(
+            self.model.borrow().matrix(),
+            &self.camera.borrow().view_projection_matrix(),
+        );
+        let mut uniform = CubeUniform::default();
+        XMStoreFloat4x4(&mut uniform.mvp, mvp);
+        self.uniforms.borrow_mut()[frame_index].slice.copy_from_slice(&[uniform]);
+    }
+}
+
+impl app::RenderLoop for CubeRenderLoop {
+    fn render(&self, app_obj: &app::App, frame_ctx: &frame::FrameContext)
+    {
+        self.update_uniform(frame_ctx.frame_index);
+
+        let clear_values = {
+            [
+                vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: [0.1, 0.1, 0.1, 1.0],
+                    }
+                },
+                vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: self.camera.borrow().depth_clear_value(),
+                        stencil: 0,
+                    }
+                },
+            ]
+        };
+        let render_pass_begin_info = {
+            vk::RenderPassBeginInfo::builder()
+                .render_pass(self.render_pass)
+                .clear_values(&clear_values)
+                .framebuffer(self.render_target.frame_buffer)
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D{x: 0, y: 0},
+                    extent: self.render_target.extent,
+                })
+                .build()
+        };
+
+        let backend = app_obj.backend.borrow();
+        let device = &backend.device;
+        let cmd_buf = frame_ctx.cmd_buffer;
+        let label = backend.cmd_label(cmd_buf, "cube");
+        let scope = app_obj.profiler.scope(cmd_buf, "cube");
+        unsafe {
+            device.cmd_begin_render_pass(
+                cmd_buf,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE
+            );
+            device.cmd_bind_pipeline(
+                cmd_buf,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pso_obj.pipeline
+            );
+            device.cmd_set_viewport(
+                cmd_buf,
+                0,
+                &self.pso_obj.pso_desc.viewports,
+            );
+            device.cmd_set_scissor(
+                cmd_buf,
+                0,
+                &self.pso_obj.pso_desc.scissors
+            );
+            device.cmd_bind_descriptor_sets(
+                cmd_buf,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pso_obj.pipeline_layout,
+                0,
+                &[self.descriptor_sets[frame_ctx.frame_index]],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(
+                cmd_buf,
+                0,
+                &[app_obj.buf_mgr_sys.vertex_buffer.buffer],
+                &[self.vb.offset],
+            );
+            device.cmd_bind_index_buffer(
+                cmd_buf,
+                app_obj.buf_mgr_sys.index_buffer.buffer,
+                self.ib.offset,
+                vk::IndexType::UINT16
+            );
+            device.cmd_draw_indexed(
+                cmd_buf,
+                self.ib_count,
+                1, 0, 0, 1
+            );
+            device.cmd_end_render_pass(
+                cmd_buf,
+            );
+        }
+        drop(scope);
+        drop(label);
+
+        self.screen.render(app_obj, frame_ctx, "cube_screen");
+    }
+
+    // 左键拖动旋转视角，滚轮缩放，立方体按rotation_speed自转
+    fn update(&self, app_obj: &app::App, delta_time: f64)
+    {
+        self.orbit.borrow_mut().update(&mut self.camera.borrow_mut(), &app_obj.input(), delta_time);
+        self.model.borrow_mut().rotate(
+            XMVectorSet(0.0, 1.0, 0.0, 0.0),
+            self.rotation_speed * delta_time as f32,
+        );
+    }
+
+    fn resize(&mut self, app_obj: &app::App, extent: vk::Extent2D) -> Result<()>
+    {
+        self.render_target = render_target::RenderTarget::new(
+            &app_obj.backend.borrow(),
+            &self.pso_obj,
+            extent,
+        )?;
+        self.pso_obj.pso_desc.set_viewport_extent(extent);
+        self.screen.set_source(self.render_target.color_view);
+        self.camera.borrow_mut().aspect = extent.width as f32 / extent.height as f32;
+        Ok(())
+    }
+
+    fn render_target(&self) -> Option<&render_target::RenderTarget>
+    {
+        Some(&self.render_target)
+    }
+
+    fn pipeline_state_objects(&mut self) -> Vec<&mut pso::PipelineStateObject>
+    {
+        vec![&mut self.pso_obj]
+    }
+}
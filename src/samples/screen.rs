@@ -0,0 +1,183 @@
+use ash::vk;
+use ash::version::*;
+use std::mem;
+use crate::app;
+use crate::base::*;
+use crate::base::descriptor::DescriptorSetWriter;
+use crate::base::error::Result;
+
+// 把离屏结果画到swapchain图像上的全屏四边形，各样例共用
+pub struct ScreenQuad {
+    pub vb: buffer::BufferSlice<f32>,
+    pub ib: buffer::BufferSlice<u16>,
+    pub ib_count: u32,
+    pub sampler: vk::Sampler,
+    // surface pso的set 0，无窗口模式下为null
+    pub descriptor_set: vk::DescriptorSet,
+    device: ash::Device,
+}
+
+impl ScreenQuad {
+    // source为要显示的离屏color图像，需要处于SHADER_READ_ONLY_OPTIMAL
+    pub fn new(app_obj: &mut app::App, source: vk::ImageView) -> Result<Self>
+    {
+        let vertices = [
+            0.0, 0.0,
+            1.0, 0.0,
+            1.1, 1.1,
+            0.0, 1.0,
+        ];
+        let vb_size = (vertices.len() * mem::size_of::<f32>()) as u64;
+        let mut vb = app_obj.buf_mgr_sys.allocate_vertex_buffer::<f32>(vb_size);
+        vb.slice.copy_from_slice(&vertices);
+
+        let ib_data = [0u16, 1, 2, 0, 2, 3];
+        let ib_size = (ib_data.len() * mem::size_of::<u16>()) as u64;
+        let mut ib = app_obj.buf_mgr_sys.allocate_index_buffer(ib_size);
+        ib.slice.copy_from_slice(&ib_data);
+
+        let backend = app_obj.backend.borrow().clone();
+        let sampler = {
+            let sampler_ci = vk::SamplerCreateInfo {
+                mag_filter: vk::Filter::LINEAR,
+                min_filter: vk::Filter::LINEAR,
+                mipmap_mode: vk::SamplerMipmapMode::NEAREST,
+                address_mode_u: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                address_mode_v: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                address_mode_w: vk::SamplerAddressMode::CLAMP_TO_EDGE,
+                max_lod: 1.0,
+                ..Default::default()
+            };
+            unsafe {
+                backend.device.create_sampler(&sampler_ci, None)?
+            }
+        };
+        backend.set_object_name(sampler, "screen_sampler");
+        let descriptor_set = match &app_obj.surface {
+            Some(surface) => {
+                let set_layout = surface.borrow().surface_pso_obj.descriptor_set_layouts[0];
+                app_obj.descriptor_allocator.allocate(set_layout)?
+            },
+            None => vk::DescriptorSet::null(),
+        };
+
+        let screen = ScreenQuad {
+            vb,
+            ib,
+            ib_count: ib_data.len() as u32,
+            sampler,
+            descriptor_set,
+            device: backend.device.clone(),
+        };
+        screen.set_source(source);
+        Ok(screen)
+    }
+
+    // 离屏图像重建后调用，此时device需要空闲
+    pub fn set_source(&self, source: vk::ImageView)
+    {
+        if self.descriptor_set == vk::DescriptorSet::null() {
+            return;
+        }
+        DescriptorSetWriter::new(self.descriptor_set)
+            .sampled_image(0, source, vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL)
+            .sampler(1, self.sampler)
+            .update(&self.device);
+    }
+
+    // 无窗口模式或这一帧没有获取到swapchain图像时不录制
+    pub fn render(&self, app_obj: &app::App, frame_ctx: &frame::FrameContext, name: &str)
+    {
+        let surface = match (&app_obj.surface, frame_ctx.image_index) {
+            (Some(surface), Some(_)) => surface.borrow(),
+            _ => return,
+        };
+
+        let clear_values = {
+            [
+                vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: [0.0, 0.0, 0.0, 0.0],
+                    }
+                },
+            ]
+        };
+
+        let render_pass_begin_info = {
+            vk::RenderPassBeginInfo::builder()
+                .render_pass(surface.surface_pso_obj.render_pass)
+                .clear_values(&clear_values)
+                .framebuffer(frame_ctx.frame_buffer)
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D{x: 0, y: 0},
+                    extent: surface.surface_resolution,
+                })
+                .build()
+        };
+
+        let backend = app_obj.backend.borrow();
+        let device = &backend.device;
+        let cmd_buf = frame_ctx.cmd_buffer;
+        let _label = backend.cmd_label(cmd_buf, name);
+        let _scope = app_obj.profiler.scope(cmd_buf, name);
+
+        unsafe {
+            device.cmd_begin_render_pass(
+                cmd_buf,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE
+            );
+            device.cmd_bind_pipeline(
+                cmd_buf,
+                vk::PipelineBindPoint::GRAPHICS,
+                surface.surface_pso_obj.pipeline
+            );
+            device.cmd_set_viewport(
+                cmd_buf,
+                0,
+                &surface.surface_pso_obj.pso_desc.viewports,
+            );
+            device.cmd_set_scissor(
+                cmd_buf,
+                0,
+                &surface.surface_pso_obj.pso_desc.scissors
+            );
+            device.cmd_bind_descriptor_sets(
+                cmd_buf,
+                vk::PipelineBindPoint::GRAPHICS,
+                surface.surface_pso_obj.pipeline_layout,
+                0,
+                &[self.descriptor_set],
+                &[],
+            );
+            device.cmd_bind_vertex_buffers(
+                cmd_buf,
+                0,
+                &[app_obj.buf_mgr_sys.vertex_buffer.buffer],
+                &[self.vb.offset],
+            );
+            device.cmd_bind_index_buffer(
+                cmd_buf,
+                app_obj.buf_mgr_sys.index_buffer.buffer,
+                self.ib.offset,
+                vk::IndexType::UINT16
+            );
+            device.cmd_draw_indexed(
+                cmd_buf,
+                self.ib_count,
+                1, 0, 0, 1
+            );
+            device.cmd_end_render_pass(
+                cmd_buf,
+            );
+        }
+    }
+}
+
+impl Drop for ScreenQuad {
+    fn drop(&mut self) {
+        unsafe {
+            self.device.destroy_sampler(self.sampler, None);
+        }
+    }
+}
@@ -0,0 +1,268 @@
+use ash::vk;
+use ash::version::*;
+use std::default::Default;
+use std::ffi::CString;
+use std::{mem, boxed};
+use crate::app;
+use crate::offset_of;
+use crate::base::*;
+use crate::base::pso::ShaderProgramDescriptor;
+use crate::base::error::Result;
+use super::screen::ScreenQuad;
+
+#[derive(Clone, Debug, Copy)]
+pub struct Vertex {
+    pub pos: [f32; 4],
+    pub color: [f32; 4],
+}
+
+pub struct TriangleRenderLoop {
+    pub device: ash::Device,
+    pub render_pass: vk::RenderPass,
+    pub render_target: render_target::RenderTarget,
+    pub pso_obj: boxed::Box<pso::PipelineStateObject>,
+    pub vb: buffer::BufferSlice<Vertex>,
+    pub ib: buffer::BufferSlice<u16>,
+    pub ib_count: u32,
+    pub screen: ScreenQuad,
+}
+
+impl TriangleRenderLoop {
+    pub fn new(app_obj: &mut app::App) -> Result<Self>
+    {
+        let resolution = app_obj.resolution();
+        // attachment
+        let render_attachment = {
+            vec![
+                vk::AttachmentDescription {
+                    format: vk::Format::R8G8B8A8_UNORM,
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    load_op: vk::AttachmentLoadOp::CLEAR,
+                    store_op: vk::AttachmentStoreOp::STORE,
+                    final_layout: vk::ImageLayout::SHADER_READ_ONLY_OPTIMAL,
+                    ..Default::default()
+                },
+                vk::AttachmentDescription {
+                    format: vk::Format::D16_UNORM,
+                    samples: vk::SampleCountFlags::TYPE_1,
+                    load_op: vk::AttachmentLoadOp::CLEAR,
+                    initial_layout: vk::ImageLayout::UNDEFINED,
+                    final_layout: vk::ImageLayout::DEPTH_STENCIL_ATTACHMENT_OPTIMAL,
+                    ..Default::default()
+                },
+            ]
+        };
+        // vert input binding desc
+        let vert_input_binding_desc = {
+            vec![
+                vk::VertexInputBindingDescription {
+                    binding: 0,
+                    stride: mem::size_of::<Vertex>() as u32,
+                    input_rate: vk::VertexInputRate::VERTEX,
+                }
+            ]
+        };
+        // vert input attr desc
+        let vert_input_attr_desc = {
+            vec![
+                vk::VertexInputAttributeDescription {
+                    location: 0,
+                    binding: 0,
+                    format: vk::Format::R32G32_SFLOAT,
+                    offset: offset_of!(Vertex, pos) as u32,
+                },
+                vk::VertexInputAttributeDescription {
+                    location: 1,
+                    binding: 0,
+                    format: vk::Format::R32G32B32A32_SFLOAT,
+                    offset: offset_of!(Vertex, color) as u32,
+                }
+            ]
+        };
+        let pso_desc = pso::PipelineStateObjectDescriptor {
+            name: "triangle_pso".to_string(),
+            vs_desc: ShaderProgramDescriptor {
+                path: "./shader/triangle/triangle.vert".to_string(),
+                entry: CString::new("main").unwrap(),
+                ..Default::default()
+            },
+            ps_desc: ShaderProgramDescriptor {
+                path: "./shader/triangle/triangle.frag".to_string(),
+                entry: CString::new("main").unwrap(),
+                ..Default::default()
+            },
+            attachment_desc: render_attachment, // move
+            viewports: vec![vk::Viewport {
+                x: 0.0, y: 0.0,
+                width: resolution.width as f32,
+                height: resolution.height as f32,
+                min_depth: 0.0,
+                max_depth: 1.0,
+            }],
+            scissors: vec![vk::Rect2D {
+                offset: vk::Offset2D { x: 0, y: 0 },
+                extent: resolution,
+            }],
+            input_binding_desc: vert_input_binding_desc,
+            input_attr_desc: vert_input_attr_desc,
+            ..Default::default()
+        };
+        let pso_obj = utility::create_pipeline_state_object(&app_obj.backend.borrow(), &pso_desc)?;
+        let render_target = render_target::RenderTarget::new(
+            &app_obj.backend.borrow(),
+            &pso_obj,
+            resolution,
+        )?;
+
+        // vertex buffer
+        let vertices = {
+            vec![
+                Vertex {
+                    pos: [-1.0, 1.0, 0.0, 1.0],
+                    color: [0.0, 1.0, 0.0, 1.0],
+                },
+                Vertex {
+                    pos: [1.0, 1.0, 0.0, 1.0],
+                    color: [0.0, 0.0, 1.0, 1.0],
+                },
+                Vertex {
+                    pos: [0.0, -1.0, 0.0, 1.0],
+                    color: [1.0, 0.0, 0.0, 1.0],
+                },
+            ]
+        };
+        let vb_size = (vertices.len() * mem::size_of::<Vertex>()) as u64;
+        let mut vb = app_obj.buf_mgr_sys.allocate_vertex_buffer::<Vertex>(vb_size);
+        vb.slice.copy_from_slice(&vertices);
+        // index buffer
+        let ib_data = [0u16, 1, 2];
+        let ib_size = (ib_data.len() * mem::size_of::<u16>()) as u64;
+        let mut ib = app_obj.buf_mgr_sys.allocate_index_buffer(ib_size);
+        ib.slice.copy_from_slice(&ib_data);
+
+        let screen = ScreenQuad::new(app_obj, render_target.color_view)?;
+
+        Ok(TriangleRenderLoop {
+            device: app_obj.backend.borrow().device.clone(),
+            render_pass: pso_obj.render_pass,
+            render_target,
+            pso_obj,
+            vb,
+            ib,
+            ib_count: ib_data.len() as u32,
+            screen,
+        })
+    }
+}
+
+impl app::RenderLoop for TriangleRenderLoop {
+    fn render(&self, app_obj: &app::App, frame_ctx: &frame::FrameContext)
+    {
+        let clear_values = {
+            [
+                vk::ClearValue {
+                    color: vk::ClearColorValue {
+                        float32: [0.0, 0.0, 0.0, 0.0],
+                    }
+                },
+                vk::ClearValue {
+                    depth_stencil: vk::ClearDepthStencilValue {
+                        depth: 1.0,
+                        stencil: 0,
+                    }
+                },
+            ]
+        };
+        let render_pass_begin_info = {
+            vk::RenderPassBeginInfo::builder()
+                .render_pass(self.render_pass)
+                .clear_values(&clear_values)
+                .framebuffer(self.render_target.frame_buffer)
+                .render_area(vk::Rect2D {
+                    offset: vk::Offset2D{x: 0, y: 0},
+                    extent: self.render_target.extent,
+                })
+                .build()
+        };
+
+        let backend = app_obj.backend.borrow();
+        let device = &backend.device;
+        let cmd_buf = frame_ctx.cmd_buffer;
+        let label = backend.cmd_label(cmd_buf, "triangle");
+        let scope = app_obj.profiler.scope(cmd_buf, "triangle");
+        unsafe {
+            device.cmd_begin_render_pass(
+                cmd_buf,
+                &render_pass_begin_info,
+                vk::SubpassContents::INLINE
+            );
+            device.cmd_bind_pipeline(
+                cmd_buf,
+                vk::PipelineBindPoint::GRAPHICS,
+                self.pso_obj.pipeline
+            );
+            device.cmd_set_viewport(
+                cmd_buf,
+                0,
+                &self.pso_obj.pso_desc.viewports,
+            );
+            device.cmd_set_scissor(
+                cmd_buf,
+                0,
+                &self.pso_obj.pso_desc.scissors
+            );
+            device.cmd_bind_vertex_buffers(
+                cmd_buf,
+                0,
+                &[app_obj.buf_mgr_sys.vertex_buffer.buffer],
+                &[self.vb.offset],
+            );
+            device.cmd_bind_index_buffer(
+                cmd_buf,
+                app_obj.buf_mgr_sys.index_buffer.buffer,
+                self.ib.offset,
+                vk::IndexType::UINT16
+            );
+            device.cmd_draw_indexed(
+                cmd_buf,
+                self.ib_count,
+                1, 0, 0, 1
+            );
+            device.cmd_end_render_pass(
+                cmd_buf,
+            );
+        }
+        drop(scope);
+        drop(label);
+
+        self.screen.render(app_obj, frame_ctx, "triangle_screen");
+    }
+
+    fn update(&self, _app_obj: &app::App, _delta_time: f64)
+    {
+
+    }
+
+    // 离屏渲染目标跟随窗口尺寸
+    fn resize(&mut self, app_obj: &app::App, extent: vk::Extent2D) -> Result<()>
+    {
+        self.render_target = render_target::RenderTarget::new(
+            &app_obj.backend.borrow(),
+            &self.pso_obj,
+            extent,
+        )?;
+        self.pso_obj.pso_desc.set_viewport_extent(extent);
+        self.screen.set_source(self.render_target.color_view);
+        Ok(())
+    }
+
+    fn render_target(&self) -> Option<&render_target::RenderTarget>
+    {
+        Some(&self.render_target)
+    }
+
+    fn pipeline_state_objects(&mut self) -> Vec<&mut pso::PipelineStateObject>
+    {
+        vec![&mut self.pso_obj]
+    }
+}
@@ -0,0 +1,81 @@
+use ash::vk;
+use std::path::Path;
+
+// 参考图像目录，设置环境变量UPDATE_GOLDEN=1重新生成
+const GOLDEN_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/");
+// 对比失败时输出实际图像和差异图像的目录
+const GOLDEN_OUTPUT_DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/target/golden/");
+
+pub struct ImageDiff {
+    pub mismatched_pixels: usize,
+    pub max_channel_diff: u8,
+    // RGBA8，不同的像素标红，相同的像素按灰度淡化显示
+    pub diff_image: Vec<u8>,
+}
+
+// 逐像素对比两张RGBA8图像，任一通道差值超过tolerance即视为不同
+pub fn compare_images(actual: &[u8], expected: &[u8], tolerance: u8) -> ImageDiff
+{
+    assert_eq!(actual.len(), expected.len(), "image size mismatch");
+    let mut mismatched_pixels = 0;
+    let mut max_channel_diff = 0u8;
+    let mut diff_image = Vec::with_capacity(actual.len());
+    for (a, e) in actual.chunks(4).zip(expected.chunks(4)) {
+        let pixel_diff = a.iter()
+            .zip(e.iter())
+            .map(|(&x, &y)| (x as i16 - y as i16).unsigned_abs() as u8)
+            .max()
+            .unwrap_or(0);
+        max_channel_diff = std::cmp::max(max_channel_diff, pixel_diff);
+        if pixel_diff > tolerance {
+            mismatched_pixels += 1;
+            diff_image.extend_from_slice(&[255, 0, 0, 255]);
+        } else {
+            let gray = ((e[0] as u32 + e[1] as u32 + e[2] as u32) / 12) as u8;
+            diff_image.extend_from_slice(&[gray, gray, gray, 255]);
+        }
+    }
+    ImageDiff {
+        mismatched_pixels,
+        max_channel_diff,
+        diff_image,
+    }
+}
+
+// 与tests/golden/{name}.png对比，失败时在target/golden/下输出实际图像和差异图像
+// 参考图像缺失时失败，设置UPDATE_GOLDEN时写入参考图像
+pub fn assert_golden(name: &str, extent: vk::Extent2D, pixels: &[u8], tolerance: u8)
+{
+    let reference_path = format!("{}{}.png", GOLDEN_DIR, name);
+    if std::env::var("UPDATE_GOLDEN").is_ok() {
+        std::fs::create_dir_all(GOLDEN_DIR).unwrap();
+        image::save_buffer(&reference_path, pixels, extent.width, extent.height, image::RGBA(8))
+            .expect("failed to write golden image");
+        return;
+    }
+    if !Path::new(&reference_path).exists() {
+        panic!("golden image {} not found, run with UPDATE_GOLDEN=1 to create it", reference_path);
+    }
+
+    let reference = image::open(&reference_path)
+        .unwrap_or_else(|e| panic!("failed to load golden image {}: {:?}", reference_path, e))
+        .to_rgba();
+    assert_eq!(
+        reference.dimensions(), (extent.width, extent.height),
+        "golden image {} size mismatch", reference_path
+    );
+    let diff = compare_images(pixels, &reference.into_raw(), tolerance);
+    if diff.mismatched_pixels > 0 {
+        std::fs::create_dir_all(GOLDEN_OUTPUT_DIR).unwrap();
+        let actual_path = format!("{}{}.actual.png", GOLDEN_OUTPUT_DIR, name);
+        let diff_path = format!("{}{}.diff.png", GOLDEN_OUTPUT_DIR, name);
+        image::save_buffer(&actual_path, pixels, extent.width, extent.height, image::RGBA(8))
+            .unwrap();
+        image::save_buffer(&diff_path, &diff.diff_image, extent.width, extent.height, image::RGBA(8))
+            .unwrap();
+        panic!(
+            "golden image {} mismatch: {} pixels over tolerance {} (max diff {}), see {}",
+            name, diff.mismatched_pixels, tolerance, diff.max_channel_diff, diff_path
+        );
+    }
+}
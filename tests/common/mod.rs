@@ -0,0 +1,2 @@
+// 集成测试共用的辅助模块
+pub mod golden;
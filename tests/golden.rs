@@ -0,0 +1,72 @@
+use std::boxed;
+use std::cell::RefCell;
+use rt_vk_example::app;
+use rt_vk_example::samples::triangle::TriangleRenderLoop;
+use rt_vk_example::samples::cube::CubeRenderLoop;
+
+mod common;
+use common::golden;
+
+// 需要Vulkan驱动（可以是lavapipe等软件实现）和glslangValidator
+const FRAME_COUNT: u64 = 3;
+const TOLERANCE: u8 = 2;
+
+fn headless_app(name: &str) -> app::App
+{
+    let app_ci = app::AppCreateInfo {
+        app_name: name.to_string(),
+        title: name.to_string(),
+        width: 256.0,
+        height: 256.0,
+        ..Default::default()
+    };
+    app::App::new_headless(&app_ci)
+        .expect("create headless app failed")
+}
+
+#[test]
+fn test_triangle_golden()
+{
+    let mut app_obj = headless_app("triangle");
+    let triangle_rl = TriangleRenderLoop::new(&mut app_obj)
+        .expect("create triangle render loop failed");
+    app_obj.render_loop_obj = RefCell::new(boxed::Box::new(triangle_rl));
+    app_obj.run_frames(FRAME_COUNT)
+        .expect("render frames failed");
+
+    let (extent, pixels) = app_obj.read_back_frame()
+        .expect("read back failed")
+        .expect("render loop has no render target");
+    golden::assert_golden("triangle", extent, &pixels, TOLERANCE);
+    assert_eq!(app_obj.validation_error_count(), 0);
+}
+
+#[test]
+fn test_cube_golden()
+{
+    let mut app_obj = headless_app("cube");
+    let cube_rl = CubeRenderLoop::new(&mut app_obj)
+        .expect("create cube render loop failed");
+    app_obj.render_loop_obj = RefCell::new(boxed::Box::new(cube_rl));
+    // 暂停自转，输出与帧时间无关
+    app_obj.set_time_scale(0.0);
+    app_obj.run_frames(FRAME_COUNT)
+        .expect("render frames failed");
+
+    let (extent, pixels) = app_obj.read_back_frame()
+        .expect("read back failed")
+        .expect("render loop has no render target");
+    golden::assert_golden("cube", extent, &pixels, TOLERANCE);
+    assert_eq!(app_obj.validation_error_count(), 0);
+}
+
+#[test]
+fn test_compare_images()
+{
+    let expected = [10u8, 20, 30, 255, 0, 0, 0, 255];
+    let actual = [12u8, 19, 30, 255, 100, 0, 0, 255];
+    let diff = golden::compare_images(&actual, &expected, 2);
+    assert_eq!(diff.mismatched_pixels, 1);
+    assert_eq!(diff.max_channel_diff, 100);
+    assert_eq!(&diff.diff_image[4..8], &[255, 0, 0, 255]);
+}